@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{hydra, orientation};
+use crate::hydra::ControllerFrame;
+use crate::orientation::{Angles, Order, Quat};
+
+
+//
+// Calibration
+//
+// Everyone holds the wands a little differently, and no two triggers rest at
+// quite the same reading. A calibration records, for one wand:
+//
+//   neutral_rot  - orientation in the playing position, which pitch, yaw and
+//                  twist are measured from
+//   twist_range  - how far the player comfortably twists either way, in
+//                  radians, which becomes a twist of 1.0
+//   trigger_rest - reading with the trigger let go, which becomes 0.0
+//   trigger_max  - reading with the trigger squeezed, which becomes 1.0
+//   origin       - centre of the playing space, which positions are relative to
+//
+// The defaults leave the raw readings as they are. Calibrations are saved as JSON,
+// keyed by player and hand, since the SDK doesn't give us serial numbers.
+//
+
+const TRIGGER_REST_MARGIN: f32 = 0.02;   // Headroom above rest so jitter stays at zero
+const MIN_TWIST_RANGE:     f32 = 0.1;
+const MIN_TRIGGER_TRAVEL:  f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub neutral_rot:  Quat,
+    pub twist_range:  f32,
+    pub trigger_rest: f32,
+    pub trigger_max:  f32,
+    pub origin:       [f32; 3],
+}
+
+impl Default for Calibration {
+    fn default () -> Calibration {
+        Calibration {
+            neutral_rot:  orientation::IDENTITY,
+            twist_range:  1.0,
+            trigger_rest: 0.0,
+            trigger_max:  1.0,
+            origin:       [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+pub type Store = BTreeMap<String, Calibration>;
+
+// The interactive routine walks through these in order
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Step {
+    Rest,
+    Twist,
+    Trigger,
+}
+
+impl Step {
+    pub fn instructions (self) -> &'static str {
+        match self {
+            Step::Rest    => "Hold both wands where you play, relaxed, triggers released",
+            Step::Twist   => "Twist each wand as far as is comfortable, both ways",
+            Step::Trigger => "Squeeze both triggers all the way, a few times",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Routine {
+    pub player:  usize,
+    pub step:    Step,
+    pub result:  [Calibration; 2],
+    order:       Order,
+    rest:        [Average; 2],
+}
+
+// Running average of the rest pose
+#[derive(Debug, Clone, Copy, Default)]
+struct Average {
+    rot:   [f32; 4],
+    pos:   [f32; 3],
+    count: f32,
+}
+
+
+//
+// Module Functions
+//
+
+pub fn begin (player: usize, order: Order) -> Routine {
+    Routine {
+        player,
+        step:      Step::Rest,
+        result:    [Calibration::default(); 2],
+        order,
+        rest:      [Average::default(); 2],
+    }
+}
+
+// Feed in one tick of raw frames for the player being calibrated
+pub fn sample (routine: &mut Routine, frames: [&ControllerFrame; 2]) {
+    for (hand, frame) in frames.iter().enumerate() {
+        if frame.enabled == 0 {
+            continue;
+        }
+
+        let result = &mut routine.result[hand];
+
+        match routine.step {
+            Step::Rest => {
+                let rest = &mut routine.rest[hand];
+                rest.count += 1.0;
+                rest.rot = lerp_towards(rest.rot, orientation::align(frame.rot_quat, rest.rot), rest.count);
+                rest.pos = lerp_towards(rest.pos, frame.pos, rest.count);
+                result.trigger_rest = result.trigger_rest.max(frame.trigger);
+            },
+
+            Step::Twist => {
+                let roll = orientation::angles(orientation::relative(result.neutral_rot, frame.rot_quat), routine.order).roll;
+                result.twist_range = result.twist_range.max(roll.abs());
+            },
+
+            Step::Trigger => {
+                result.trigger_max = result.trigger_max.max(frame.trigger);
+            },
+        }
+    }
+}
+
+// How many rest poses each wand has given, left then right
+pub fn rest_samples (routine: &Routine) -> [usize; 2] {
+    routine.rest.map(|rest| rest.count as usize)
+}
+
+// Finish the current step, unless the rest step hasn't seen both wands yet. Returns the calibrations, left then right, once the
+// last step is done.
+pub fn advance (routine: &mut Routine) -> Option<[Calibration; 2]> {
+    match routine.step {
+        Step::Rest => {
+            // Both wands have to be seen at rest, or the origin lands on a default
+            if routine.rest.iter().any(|rest| rest.count == 0.0) {
+                return None;
+            }
+
+            let [left, right] = routine.rest;
+            let centre = lerp_towards(left.pos, right.pos, 2.0);
+
+            for (result, rest) in routine.result.iter_mut().zip(routine.rest) {
+                result.neutral_rot  = orientation::normalize(rest.rot);
+                result.trigger_rest = (result.trigger_rest + TRIGGER_REST_MARGIN).min(1.0 - MIN_TRIGGER_TRAVEL);
+                result.twist_range  = 0.0;
+                result.trigger_max  = 0.0;
+            }
+
+            // One origin for both wands, between them, so their separation survives
+            for result in routine.result.iter_mut() {
+                result.origin = centre;
+            }
+
+            routine.step = Step::Twist;
+            None
+        },
+
+        Step::Twist => {
+            for result in routine.result.iter_mut() {
+                result.twist_range = result.twist_range.max(MIN_TWIST_RANGE);
+            }
+
+            routine.step = Step::Trigger;
+            None
+        },
+
+        Step::Trigger => {
+            for result in routine.result.iter_mut() {
+                result.trigger_max = result.trigger_max.max(result.trigger_rest + MIN_TRIGGER_TRAVEL);
+            }
+
+            Some(routine.result)
+        },
+    }
+}
+
+// Where a wand's calibration lives in the store
+pub fn key (player: usize, hand: usize) -> String {
+    format!("p{}-{}", player + 1, if hand == 0 { "left" } else { "right" })
+}
+
+// Both of a player's calibrations, falling back to the defaults
+pub fn lookup (store: &Store, player: usize) -> [Calibration; 2] {
+    [0, 1].map(|hand| store.get(&key(player, hand)).copied().unwrap_or_default())
+}
+
+pub fn load (path: &str) -> io::Result<Store> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+pub fn save (path: &str, store: &Store) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, store)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+
+//
+// Applying
+//
+// Raw readings in, calibrated readings out. These are what copy_frame_to_wand
+// uses in place of the frame's own values.
+//
+
+// The calibration for whichever hand a frame comes from
+pub fn for_frame (calibration: &[Calibration; 2], frame: &ControllerFrame) -> Calibration {
+    hydra::hand_index(frame.which_hand).map(|hand| calibration[hand]).unwrap_or_default()
+}
+
+// Orientation in angles away from the neutral pose
+pub fn angles (calibration: &Calibration, rot: Quat, order: Order) -> Angles {
+    orientation::angles(orientation::relative(calibration.neutral_rot, rot), order)
+}
+
+pub fn twist (calibration: &Calibration, roll: f32) -> f32 {
+    roll / calibration.twist_range
+}
+
+pub fn trigger (calibration: &Calibration, raw: f32) -> f32 {
+    ((raw - calibration.trigger_rest) / (calibration.trigger_max - calibration.trigger_rest)).clamp(0.0, 1.0)
+}
+
+pub fn position (calibration: &Calibration, pos: [f32; 3]) -> [f32; 3] {
+    [ pos[0] - calibration.origin[0], pos[1] - calibration.origin[1], pos[2] - calibration.origin[2] ]
+}
+
+// Moves a running average of `n - 1` samples to include one more
+fn lerp_towards<const N: usize> (average: [f32; N], sample: [f32; N], n: f32) -> [f32; N] {
+    let mut out = average;
+    for (out, sample) in out.iter_mut().zip(sample) {
+        *out += (sample - *out) / n;
+    }
+    out
+}
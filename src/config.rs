@@ -0,0 +1,139 @@
+
+use std::env;
+use std::time::Duration;
+
+use crate::hydra::MAX_PLAYERS;
+use crate::logging::Level;
+use crate::orientation::Order;
+use crate::filter;
+use crate::modulation::Mapping;
+use crate::zgicabra::Hand;
+
+
+//
+// Config
+//
+// Runtime options collected from the command line. Anything not given falls back
+// to the defaults below.
+//
+
+const DEFAULT_TELEMETRY_RATE: f32 = 30.0;
+const DEFAULT_RENDER_SEED:    u64 = 0;
+const DEFAULT_JACK_LATENCY_MS: u64 = 5;
+const DEFAULT_CONTROL_RATE:    f32 = 100.0;
+const DEFAULT_UI_RATE:         f32 = 30.0;
+const DEFAULT_CALIBRATION:     &str = "calibration.json";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub telemetry_addr: Option<String>,
+    pub telemetry_rate: f32,
+    pub synth:          bool,
+    pub synth_wav:      Option<String>,
+    pub record:         Option<String>,
+    pub render:         Option<(String, String)>,
+    pub seed:           u64,
+    pub jack:           bool,
+    pub jack_latency:   Duration,
+    pub control_period: Duration,
+    pub ui_period:      Duration,
+    pub headless:       bool,
+    pub log_level:      Option<Level>,   // None is off
+    pub simulate:       Option<String>,
+    pub players:        usize,
+    pub combined:       bool,    // All players share one MIDI channel
+    pub single_wand:    Option<Hand>,   // Play one-handed even with both wands
+    pub mirrored:       [bool; MAX_PLAYERS],   // Left-handed layout, per player
+    pub calibration:    String,
+    pub rotation_order: Order,
+    pub filter:         filter::Settings,
+    pub bench_filters:  Option<String>,   // Session to benchmark the filters on, instead of playing
+    pub mappings:       Vec<Mapping>,     // Extra CCs, see modulation.rs
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            telemetry_addr: None,
+            telemetry_rate: DEFAULT_TELEMETRY_RATE,
+            synth:          false,
+            synth_wav:      None,
+            record:         None,
+            render:         None,
+            seed:           DEFAULT_RENDER_SEED,
+            jack:           false,
+            jack_latency:   Duration::from_millis(DEFAULT_JACK_LATENCY_MS),
+            control_period: Duration::from_secs_f32(1.0 / DEFAULT_CONTROL_RATE),
+            ui_period:      Duration::from_secs_f32(1.0 / DEFAULT_UI_RATE),
+            headless:       false,
+            log_level:      None,
+            simulate:       None,
+            players:        1,
+            combined:       false,
+            single_wand:    None,
+            mirrored:       [false; MAX_PLAYERS],
+            calibration:    DEFAULT_CALIBRATION.to_string(),
+            rotation_order: Order::default(),
+            filter:         filter::Settings::default(),
+            bench_filters:  None,
+            mappings:       Vec::new(),
+        }
+    }
+
+    pub fn from_args() -> Config {
+        let mut config = Config::new();
+        let mut args = env::args().skip(1);
+        let mut log_level = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--telemetry"      => config.telemetry_addr = args.next(),
+                "--telemetry-rate" => config.telemetry_rate = parse_or(args.next(), DEFAULT_TELEMETRY_RATE),
+                "--synth"          => config.synth = true,
+                "--synth-wav"      => config.synth_wav = args.next(),
+                "--record"         => config.record = args.next(),
+                "--render"         => config.render = args.next().zip(args.next()),
+                "--seed"           => config.seed = parse_or(args.next(), DEFAULT_RENDER_SEED),
+                "--jack"           => config.jack = true,
+                "--jack-latency"   => config.jack_latency = Duration::from_millis(parse_or(args.next(), DEFAULT_JACK_LATENCY_MS)),
+                "--control-rate"   => config.control_period = Duration::from_secs_f32(1.0 / parse_or(args.next(), DEFAULT_CONTROL_RATE).max(1.0)),
+                "--ui-rate"        => config.ui_period = Duration::from_secs_f32(1.0 / parse_or(args.next(), DEFAULT_UI_RATE).max(1.0)),
+                "--headless"       => config.headless = true,
+                "--simulate"       => config.simulate = args.next(),
+                "--players"        => config.players = parse_or(args.next(), 1).clamp(1, MAX_PLAYERS),
+                "--combined"       => config.combined = true,
+                "--mirror"         => config.mirrored[parse_or(args.next(), 1usize).clamp(1, MAX_PLAYERS) - 1] = true,
+                "--calibration"    => config.calibration = args.next().unwrap_or(DEFAULT_CALIBRATION.to_string()),
+                "--rotation-order" => config.rotation_order = parse_or(args.next(), Order::default()),
+                "--filter"         => config.filter.kind = parse_or(args.next(), config.filter.kind),
+                "--filter-param"   => if let Err(err) = config.filter.set(&args.next().unwrap_or_default()) { eprintln!("Config - {}", err) },
+                "--bench-filters"  => config.bench_filters = args.next(),
+                "--map"            => match args.next().unwrap_or_default().parse() {
+                    Ok(mapping) => config.mappings.push(mapping),
+                    Err(err)    => eprintln!("Config - {}", err),
+                },
+                "--single-wand"    => match args.next().as_deref() {
+                    Some("left")  => config.single_wand = Some(Hand::Left),
+                    Some("right") => config.single_wand = Some(Hand::Right),
+                    other         => eprintln!("Config - --single-wand takes left or right, got '{}'", other.unwrap_or_default()),
+                },
+                "--log-level"      => log_level = Some(args.next().and_then(|s| s.parse().ok())),   // 'off' or anything unknown disables
+                _ => eprintln!("Config - ignoring unknown argument '{}'", arg),
+            }
+        }
+
+        // Headless runs log at info unless told otherwise, the TUI doesn't log at all
+        config.log_level = log_level.unwrap_or(if config.headless { Some(Level::Info) } else { None });
+
+        config
+    }
+}
+
+
+//
+// Helpers
+//
+
+fn parse_or<T: std::str::FromStr> (arg: Option<String>, default: T) -> T {
+    arg.and_then(|s| s.parse().ok()).unwrap_or(default)
+}
@@ -0,0 +1,797 @@
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::{calibration, hydra, logging, midi, modulation, scheduler, session, space, telemetry, zgicabra};
+use crate::hydra::{HydraState, ControllerFrame, Status};
+use crate::calibration::Routine;
+use crate::modulation::Modulation;
+use crate::midi::MidiError;
+use crate::zgicabra::{Zgicabra, DeltaEvent, Filtering, Hand};
+use crate::midi_event::MidiEvent;
+use crate::telemetry::Telemetry;
+use crate::session::Recorder;
+use crate::config::Config;
+use crate::scheduler::{Scheduler, Stats, Summary};
+use crate::HISTORY_WINDOW;
+
+#[cfg(feature = "synth")]
+use crate::synth;
+
+
+//
+// Control
+//
+// Everything between the sensors and the MIDI port runs here, on its own
+// high-priority thread, at a steady rate. Nothing in this loop waits on the
+// terminal: the UI thread picks up whatever Snapshot was published most recently
+// and draws it in its own time.
+//
+
+const MIDI_DEVICE_NAME: &str = "Zgicabra";
+const CONTROL_THREAD_PRIORITY: i32 = 50;
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// What the UI thread can ask of the control thread
+pub enum Command {
+    Delta(DeltaEvent),      // Goes to every player
+    Calibrate(usize),       // Start calibrating a player, or cancel if already calibrating
+    CalibrateNext,          // Finish the current calibration step
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub sensors: Duration,
+    pub gesture: Duration,
+    pub midi:    Duration,
+    pub extras:  Duration,
+    pub total:   Duration,
+    pub latency: Duration,   // Sensor capture to MIDI dispatch
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatsReport {
+    pub period:   Summary,
+    pub latency:  Summary,
+    pub overruns: u64,
+}
+
+// One instrument, played with one base's pair of wands on its own MIDI channel
+#[derive(Clone)]
+pub struct Player {
+    pub zgicabra:     Zgicabra,
+    pub history:      Vec<Zgicabra>,
+    pub delta_events: Vec<DeltaEvent>,
+    pub channel:      u8,
+    pub status:       Status,
+}
+
+impl Player {
+    pub fn new (channel: u8, mirrored: bool) -> Player {
+        let zgicabra = Zgicabra { mirrored, ..Zgicabra::new() };
+        let mut history = Vec::with_capacity(HISTORY_WINDOW);
+
+        history.push(zgicabra.clone()); // Fill first frame to allow initial derivatives
+
+        Player {
+            zgicabra,
+            history,
+            delta_events: Vec::new(),
+            channel,
+            status:       Status::NoBase,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub players:     Vec<Player>,
+    pub midi_events: Vec<MidiEvent>,
+    pub timings:     Timings,
+    pub stats:       StatsReport,
+    pub midi_status: midi::Status,
+    pub calibration: Option<Routine>,
+}
+
+pub struct Shared {
+    pub running:   AtomicBool,
+    pub ui_closed: AtomicBool,
+    pub latest:    Mutex<Option<Snapshot>>,
+}
+
+impl Shared {
+    pub fn new() -> Shared {
+        Shared {
+            running:   AtomicBool::new(true),
+            ui_closed: AtomicBool::new(false),
+            latest:    Mutex::new(None),
+        }
+    }
+}
+
+pub struct Control {
+    pub period:        Duration,
+    pub hydra_state:   HydraState,
+    pub players:       Vec<Player>,
+    pub filtering:     Vec<Filtering>,   // One per player, see zgicabra::Filtering
+    pub midi_events:   Vec<MidiEvent>,
+    pub timings:       Timings,
+    pub period_stats:  Stats,
+    pub latency_stats: Stats,
+    pub overruns:      u64,
+    pub connection:    midi::Connection,
+    pub telemetry:     Option<Telemetry>,
+    pub recorder:      Option<Recorder>,
+    pub headless:      bool,
+    pub combined:      bool,
+    pub single_wand:   Option<Hand>,
+    pub modulation:    Vec<Modulation>,   // One per player, or just the one when combined
+    pub calibration:   Option<Routine>,
+    pub calibrations:  calibration::Store,
+    pub calibration_path: String,
+    pub saving:        Option<JoinHandle<()>>,   // Calibration being written out
+    #[cfg(feature = "synth")]
+    pub synth_output:  Option<synth::Output>,
+}
+
+impl Control {
+    pub fn new (period: Duration, hydra_state: HydraState, connection: midi::Connection, players: Vec<Player>) -> Control {
+        Control {
+            period,
+            hydra_state,
+            filtering:     players.iter().map(|_| Filtering::default()).collect(),
+            players,
+            midi_events:   Vec::new(),
+            timings:       Timings::default(),
+            period_stats:  Stats::new(),
+            latency_stats: Stats::new(),
+            overruns:      0,
+            connection,
+            telemetry:     None,
+            recorder:      None,
+            headless:      false,
+            combined:      false,
+            single_wand:   None,
+            modulation:    Vec::new(),
+            calibration:   None,
+            calibrations:  calibration::Store::new(),
+            calibration_path: String::new(),
+            saving:        None,
+            #[cfg(feature = "synth")]
+            synth_output:  None,
+        }
+    }
+}
+
+
+//
+// Module Functions
+//
+// All three run on the control thread, since some outputs (like a live audio
+// stream) have to stay on the thread that opened them.
+//
+
+pub fn start (config: &Config) -> Control {
+    say(config.headless, "Establishing MIDI connection... ");
+    let connection = connect_midi(config).unwrap();
+    say(config.headless, "✅\n");
+    logging::info("midi_connected", json!({ "device": MIDI_DEVICE_NAME, "jack": config.jack }));
+
+    let hydra_state = match config.simulate.as_ref() {
+        Some(path) => HydraState::simulated(session::load(path).unwrap()),
+        None       => HydraState::new(),
+    };
+
+    // Each player gets their own channel, unless they're playing one instrument together
+    let players = (0..config.players)
+        .map(|ix| Player::new(if config.combined { 0 } else { ix as u8 }, config.mirrored[ix]))
+        .collect();
+
+    let mut control = Control::new(config.control_period, hydra_state, connection, players);
+
+    control.headless    = config.headless;
+    control.combined    = config.combined;
+    control.single_wand = config.single_wand;
+    control.modulation  = control.players.iter().map(|_| Modulation::new(&config.mappings)).collect();
+
+    // No calibration file yet is fine, everyone starts from the defaults
+    control.calibrations     = calibration::load(&config.calibration).unwrap_or_default();
+    control.calibration_path = config.calibration.clone();
+
+    for (ix, (player, filtering)) in control.players.iter_mut().zip(control.filtering.iter_mut()).enumerate() {
+        player.zgicabra.calibration    = calibration::lookup(&control.calibrations, ix);
+        player.zgicabra.rotation_order = config.rotation_order;
+        *filtering                     = Filtering::new(config.filter);
+    }
+
+    // Telemetry's a nice-to-have: if the port's taken, play on without it
+    control.telemetry = config.telemetry_addr.as_ref().and_then(|addr| {
+        say(config.headless, &format!("Starting telemetry on {}... ", addr));
+        match telemetry::start(addr, config.telemetry_rate) {
+            Ok(telemetry) => {
+                say(config.headless, "✅\n");
+                logging::info("telemetry_started", json!({ "addr": addr, "rate": config.telemetry_rate }));
+                Some(telemetry)
+            },
+            Err(err) => {
+                say(config.headless, &format!("❌ {}\n", err));
+                logging::error("telemetry_failed", json!({ "addr": addr, "error": err.to_string() }));
+                None
+            },
+        }
+    });
+
+    #[cfg(feature = "synth")]
+    if config.synth || config.synth_wav.is_some() {
+        say(config.headless, "Starting synth... ");
+        control.synth_output = Some(synth::start(config.synth_wav.clone(), config.synth).unwrap());
+        say(config.headless, "✅\n");
+        logging::info("synth_started", json!({ "live": config.synth, "wav": config.synth_wav }));
+    }
+
+    control.recorder = config.record.as_ref().map(|path| {
+        say(config.headless, &format!("Recording session to {}... ", path));
+        let recorder = session::start_recording(path).unwrap();
+        say(config.headless, "✅\n");
+        logging::info("recording_started", json!({ "path": path }));
+        recorder
+    });
+
+    say(config.headless, "Starting sensors... ");
+    match hydra::start(&mut control.hydra_state) {
+        Ok(()) => {
+            say(config.headless, "✅\n");
+            logging::info("sensors_started", json!({ "simulated": config.simulate }));
+        },
+        Err(err) => {
+            say(config.headless, &format!("❌ {}\n", err));
+            logging::error("sensors_failed", json!({ "error": err.to_string() }));
+        },
+    }
+
+    sleep(Duration::from_millis(1000));
+
+    control
+}
+
+pub fn run (mut control: Control, shared: &Shared, commands: Receiver<Command>) -> Control {
+    raise_priority();
+
+    let mut scheduler = Scheduler::new(control.period);
+    let mut last_stats_log = Instant::now();
+
+    logging::info("running", json!({ "control_rate": 1.0 / control.period.as_secs_f32() }));
+
+    while shared.running.load(Ordering::Relaxed) {
+        for command in commands.try_iter() {
+            match command {
+                Command::Delta(delta) => {
+                    for player in control.players.iter_mut() {
+                        zgicabra::apply_command(&mut player.zgicabra, delta.clone(), &mut player.delta_events);
+                    }
+                },
+                Command::Calibrate(player) => calibrate(&mut control, player),
+                Command::CalibrateNext     => calibrate_next(&mut control),
+            }
+        }
+
+        tick(&mut control);
+        publish(&control, shared);
+
+        for (ix, player) in control.players.iter().enumerate() {
+            for delta in player.delta_events.iter() {
+                logging::debug("delta", json!({ "player": ix + 1, "delta": delta }));
+            }
+        }
+
+        if last_stats_log.elapsed() >= STATS_LOG_INTERVAL {
+            logging::info("stats", stats_fields(&stats_report(&control)));
+            last_stats_log = Instant::now();
+        }
+
+        control.midi_events.clear();
+        for player in control.players.iter_mut() {
+            player.delta_events.clear();
+        }
+
+        scheduler::wait(&mut scheduler);
+        scheduler::record(&mut control.period_stats, scheduler.interval);
+        control.overruns = scheduler.overruns;
+    }
+
+    logging::info("stopping", json!({}));
+
+    control
+}
+
+pub fn stop (mut control: Control) {
+    say(control.headless, "Stopping sensors... ");
+    hydra::stop(&mut control.hydra_state);
+    say(control.headless, "ok\n");
+
+    finish_saving(&mut control);
+
+    let stats = stats_report(&control);
+    logging::info("stats", stats_fields(&stats));
+
+    say(control.headless, &format!("Loop period:     {} ({} ticks, {} overruns)\n", stats.period, stats.period.count, stats.overruns));
+    say(control.headless, &format!("Input-to-MIDI:   {}\n", stats.latency));
+
+    if let Some(recorder) = control.recorder {
+        if let Err(err) = session::stop_recording(recorder) {
+            logging::error("recording_failed", json!({ "error": err.to_string() }));
+        }
+    }
+
+    say(control.headless, "Closing connection... ");
+    midi::close(control.connection);
+    say(control.headless, "ok\n");
+
+    #[cfg(feature = "synth")]
+    if let Some(output) = control.synth_output {
+        say(control.headless, "Stopping synth... ");
+        synth::stop(output).unwrap();
+        say(control.headless, "ok\n");
+    }
+
+    logging::info("stopped", json!({}));
+}
+
+pub fn tick (control: &mut Control) {
+    let start = Instant::now();
+
+    hydra::update(&mut control.hydra_state);
+
+    // A full disk mustn't stop the show: give up on the recording and play on
+    if let Some(recorder) = control.recorder.as_mut() {
+        if let Err(err) = session::record(recorder, &control.hydra_state) {
+            logging::error("recording_failed", json!({ "error": err.to_string() }));
+            control.recorder = None;
+        }
+    }
+
+    if let Some(routine) = control.calibration.as_mut() {
+        let (left, right) = hydra::wands(&control.hydra_state, routine.player);
+        let space = &control.players[routine.player].zgicabra.space;
+        calibration::sample(routine, [&space::apply(space, left), &space::apply(space, right)]);
+    }
+
+    let sensors_done = Instant::now();
+
+    for (ix, (player, filtering)) in control.players.iter_mut().zip(control.filtering.iter_mut()).enumerate() {
+        let status = control.hydra_state.status[ix];
+        let wands  = hydra::wands(&control.hydra_state, ix);
+
+        // Nobody plays while they're being calibrated
+        if control.calibration.as_ref().is_some_and(|routine| routine.player == ix) {
+            player.status = status;
+            zgicabra::release(&mut player.zgicabra, filtering, &mut player.delta_events);
+            continue;
+        }
+
+        play(player, filtering, ix, status, wands, control.single_wand, control.hydra_state.timedelta);
+    }
+
+    let gesture_done = Instant::now();
+
+    if control.combined {
+        midi::update(lead(&control.players), &mut control.modulation[0], &combined_deltas(&control.players), &mut control.midi_events);
+        on_channel(&mut control.midi_events, control.players[0].channel);
+    } else {
+        for (player, modulation) in control.players.iter().zip(control.modulation.iter_mut()) {
+            let first = control.midi_events.len();
+            midi::update(&player.zgicabra, modulation, &player.delta_events, &mut control.midi_events);
+            on_channel(&mut control.midi_events[first..], player.channel);
+        }
+    }
+
+    match midi::maintain(&mut control.connection) {
+        Some(midi::Change::Lost(err)) => {
+            logging::warn("midi_lost", json!({ "error": err.to_string() }));
+        },
+        Some(midi::Change::Reconnected) => {
+            logging::info("midi_reconnected", json!({}));
+
+            for modulation in control.modulation.iter_mut() {
+                modulation::forget(modulation);
+            }
+
+            let mut restore = Vec::new();
+            if control.combined {
+                let lead = lead(&control.players);
+                restore = midi::restore_events(lead);
+
+                for note in held(control.players.iter().map(|player| &player.zgicabra)) {
+                    if !(lead.note.on && lead.note.current == note) {
+                        restore.push(MidiEvent::note_on(note, 127));
+                    }
+                }
+                on_channel(&mut restore, control.players[0].channel);
+            } else {
+                for player in control.players.iter() {
+                    let mut events = midi::restore_events(&player.zgicabra);
+                    on_channel(&mut events, player.channel);
+                    restore.extend(events);
+                }
+            }
+
+            let _ = midi::dispatch(&restore, &mut control.connection, control.hydra_state.timestamp);
+        },
+        None => {},
+    }
+
+    match midi::dispatch(&control.midi_events, &mut control.connection, control.hydra_state.timestamp) {
+        Ok(()) | Err(MidiError::NotConnected) => {},
+        Err(err @ MidiError::Disconnected(_)) => logging::warn("midi_lost", json!({ "error": err.to_string() })),
+        Err(err) => logging::warn("midi_send_failed", json!({ "error": err.to_string() })),
+    }
+
+    let midi_done = Instant::now();
+    let latency   = midi_done - control.hydra_state.timestamp;
+
+    scheduler::record(&mut control.latency_stats, latency);
+
+    #[cfg(feature = "synth")]
+    if let Some(output) = control.synth_output.as_mut() {
+        synth::update(output, &control.midi_events, control.hydra_state.timedelta);
+    }
+
+    if let Some(telemetry) = control.telemetry.as_mut() {
+        telemetry::update(telemetry, &control.players, &control.midi_events);
+    }
+
+    for player in control.players.iter_mut() {
+        if player.history.len() >= HISTORY_WINDOW {
+            player.history.remove(0);
+        }
+        player.history.push(player.zgicabra.clone());
+    }
+
+    let extras_done = Instant::now();
+
+    control.timings = Timings {
+        sensors: sensors_done - start,
+        gesture: gesture_done - sensors_done,
+        midi:    midi_done    - gesture_done,
+        extras:  extras_done  - midi_done,
+        total:   extras_done  - start,
+        latency,
+    };
+}
+
+
+//
+// Helpers
+//
+
+#[cfg(feature = "jack")]
+fn connect_midi (config: &Config) -> Result<midi::Connection, MidiError> {
+    if config.jack {
+        midi::connect_jack(MIDI_DEVICE_NAME, config.jack_latency)
+    } else {
+        midi::connect_midir(MIDI_DEVICE_NAME)
+    }
+}
+
+#[cfg(not(feature = "jack"))]
+fn connect_midi (config: &Config) -> Result<midi::Connection, MidiError> {
+    if config.jack {
+        say(config.headless, "JACK output needs `--features jack`, falling back to ALSA...\n");
+        logging::warn("jack_unavailable", json!({}));
+    }
+    midi::connect_midir(MIDI_DEVICE_NAME)
+}
+
+// One player's tick. A player pauses while their base or both wands are missing,
+// and picks up again by itself once they're back. With only one wand left they
+// carry on one-handed.
+fn play (player: &mut Player, filtering: &mut Filtering, ix: usize, status: Status, (left, right): (&ControllerFrame, &ControllerFrame), single_wand: Option<Hand>, dt: Duration) {
+    let was = player.status;
+    player.status = status;
+
+    let hand = match status {
+        Status::Ready   => single_wand.unwrap_or(Hand::Neither),
+        Status::OneWand => if hydra::wand_ready(left, 0) { Hand::Left } else { Hand::Right },
+        _ => {
+            if was.playable() {
+                logging::warn("sensors_lost", json!({ "player": ix + 1, "status": status }));
+                zgicabra::release(&mut player.zgicabra, filtering, &mut player.delta_events);
+            }
+            return;
+        },
+    };
+
+    if !was.playable() {
+        logging::info("sensors_ready", json!({ "player": ix + 1 }));
+    }
+
+    // Switching between one and two hands changes what everything means, so
+    // let go first rather than jump. The first tick in the new mode then starts
+    // from the released state, or a held trigger would read as still held.
+    let released;
+    let prev = if hand != player.zgicabra.one_hand {
+        logging::info("play_mode", json!({ "player": ix + 1, "one_hand": hand }));
+        zgicabra::release(&mut player.zgicabra, filtering, &mut player.delta_events);
+        released = player.zgicabra.clone();
+        &released
+    } else {
+        player.history.last().unwrap()
+    };
+
+    match hand {
+        Hand::Neither => zgicabra::update(&mut player.zgicabra, prev, filtering, left, right, dt, &mut player.delta_events),
+        Hand::Left    => zgicabra::update_one_handed(&mut player.zgicabra, prev, filtering, left, hand, dt, &mut player.delta_events),
+        Hand::Right   => zgicabra::update_one_handed(&mut player.zgicabra, prev, filtering, right, hand, dt, &mut player.delta_events),
+    }
+
+    if player.zgicabra.space != prev.space {
+        logging::info("recentered", json!({ "player": ix + 1, "space": player.zgicabra.space }));
+
+        if prev.calibration.iter().any(|calibration| calibration.origin != [0.0, 0.0, 0.0]) {
+            logging::info("calibrated_origin_overridden", json!({ "player": ix + 1, "origins": prev.calibration.map(|calibration| calibration.origin) }));
+        }
+    }
+}
+
+
+//
+// Combined Play
+//
+// With --combined every player is one instrument on one channel, so the channel
+// should hear one performance, not two fighting over it. The loudest player leads
+// bend, level and the mapped CCs. A note sounds while anyone is holding it: it
+// starts when the first player reaches it and ends when the last one lets go.
+//
+
+fn lead (players: &[Player]) -> &Zgicabra {
+    players.iter()
+        .map(|player| &player.zgicabra)
+        .reduce(|a, b| if b.level > a.level { b } else { a })
+        .unwrap()
+}
+
+fn held<'a> (states: impl Iterator<Item = &'a Zgicabra>) -> Vec<u8> {
+    let mut notes: Vec<u8> = states.filter(|state| state.note.on).map(|state| state.note.current).collect();
+    notes.sort_unstable();
+    notes.dedup();
+    notes
+}
+
+// Everyone's deltas as one player's: each command once, and notes only where the
+// set of held notes changed since last tick
+fn combined_deltas (players: &[Player]) -> Vec<DeltaEvent> {
+    let before = held(players.iter().map(|player| player.history.last().unwrap()));
+    let after  = held(players.iter().map(|player| &player.zgicabra));
+    let mut deltas = Vec::new();
+
+    for delta in players.iter().flat_map(|player| player.delta_events.iter()) {
+        let note = matches!(delta, DeltaEvent::NoteStart(_) | DeltaEvent::NoteChange(_, _) | DeltaEvent::NoteEnd(_));
+
+        if !note && !deltas.contains(delta) {
+            deltas.push(delta.clone());
+        }
+    }
+
+    deltas.extend(before.iter().filter(|note| !after.contains(note)).map(|note| DeltaEvent::NoteEnd(*note)));
+    deltas.extend(after.iter().filter(|note| !before.contains(note)).map(|note| DeltaEvent::NoteStart(*note)));
+    deltas
+}
+
+// Setup and shutdown progress, for whoever's at the terminal. Headless there's
+// nobody, and each step logs an event instead.
+fn say (headless: bool, text: &str) {
+    if !headless {
+        print!("{}", text);
+        let _ = io::stdout().flush();
+    }
+}
+
+// Hand the latest state to the UI. If the UI hasn't collected the previous one yet
+// its events are carried forward, and if the UI is mid-read we skip this tick
+// rather than wait on it.
+fn publish (control: &Control, shared: &Shared) {
+    if let Ok(mut latest) = shared.latest.try_lock() {
+        let mut players     = control.players.clone();
+        let mut midi_events = Vec::new();
+
+        if let Some(previous) = latest.take() {
+            for (player, previous) in players.iter_mut().zip(previous.players) {
+                let current = std::mem::replace(&mut player.delta_events, previous.delta_events);
+                player.delta_events.extend(current);
+            }
+            midi_events = previous.midi_events;
+        }
+
+        midi_events.extend_from_slice(&control.midi_events);
+
+        *latest = Some(Snapshot {
+            players,
+            midi_events,
+            timings:     control.timings,
+            stats:       stats_report(control),
+            midi_status: control.connection.status,
+            calibration: control.calibration.clone(),
+        });
+    }
+}
+
+fn calibrate (control: &mut Control, player: usize) {
+    if control.calibration.take().is_some() {
+        logging::info("calibration_cancelled", json!({}));
+    } else if player < control.players.len() {
+        control.calibration = Some(calibration::begin(player, control.players[player].zgicabra.rotation_order));
+        logging::info("calibration_started", json!({ "player": player + 1 }));
+    }
+}
+
+// Once the last step is done the new calibration is used straight away, and
+// saved for next time
+fn calibrate_next (control: &mut Control) {
+    let Some(routine) = control.calibration.as_mut() else { return };
+    let player = routine.player;
+
+    let step = routine.step;
+
+    let Some(result) = calibration::advance(routine) else {
+        if routine.step == step {
+            logging::warn("calibration_waiting", json!({ "player": player + 1, "step": step, "samples": calibration::rest_samples(routine) }));
+        } else {
+            logging::info("calibration_step", json!({ "player": player + 1, "step": routine.step }));
+        }
+        return;
+    };
+
+    control.calibration = None;
+    control.players[player].zgicabra.calibration = result;
+
+    for (hand, calibration) in result.iter().enumerate() {
+        control.calibrations.insert(calibration::key(player, hand), *calibration);
+    }
+
+    // Disks can stall, so the file's written off the control thread. Saves are
+    // kept in order by waiting on the last one, which is long done by now.
+    let path  = control.calibration_path.clone();
+    let store = control.calibrations.clone();
+
+    finish_saving(control);
+    control.saving = Some(thread::spawn(move || {
+        match calibration::save(&path, &store) {
+            Ok(())   => logging::info("calibration_saved", json!({ "player": player + 1, "path": path, "calibration": result })),
+            Err(err) => logging::error("calibration_save_failed", json!({ "path": path, "error": err.to_string() })),
+        }
+    }));
+}
+
+fn finish_saving (control: &mut Control) {
+    if let Some(saving) = control.saving.take() {
+        let _ = saving.join();
+    }
+}
+
+fn on_channel (midi_events: &mut [MidiEvent], channel: u8) {
+    for event in midi_events.iter_mut() {
+        *event = event.on_channel(channel);
+    }
+}
+
+fn stats_report (control: &Control) -> StatsReport {
+    StatsReport {
+        period:   scheduler::summary(&control.period_stats),
+        latency:  scheduler::summary(&control.latency_stats),
+        overruns: control.overruns,
+    }
+}
+
+fn stats_fields (stats: &StatsReport) -> Value {
+    fn ms (d: Duration) -> f32 { d.as_secs_f32() * 1000.0 }
+    fn summary (s: &Summary) -> Value {
+        json!({ "min_ms": ms(s.min), "avg_ms": ms(s.avg), "max_ms": ms(s.max), "p99_ms": ms(s.p99) })
+    }
+
+    json!({
+        "ticks":    stats.period.count,
+        "overruns": stats.overruns,
+        "period":   summary(&stats.period),
+        "latency":  summary(&stats.latency),
+    })
+}
+
+// Ask for realtime scheduling. Without the right privileges this quietly fails
+// and we carry on at normal priority.
+fn raise_priority () {
+    unsafe {
+        let param = libc::sched_param { sched_priority: CONTROL_THREAD_PRIORITY };
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param);
+    }
+}
+
+
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::fixtures;
+
+    // The right wand drops out while both triggers are up, then both wands drop
+    // out mid-note. Runs the simulated backend the way `tick` does.
+    #[test]
+    fn dropouts_pause_and_release () {
+        let mut frames = fixtures::performance(400);
+        for (tick, frame) in frames.iter_mut().enumerate() {
+            if (120..140).contains(&tick) { frame.controllers[1].enabled = 0; }
+            if (260..280).contains(&tick) { frame.controllers.iter_mut().for_each(|wand| wand.enabled = 0); }
+        }
+
+        let mut hydra_state = HydraState::simulated(frames);
+        let mut player      = Player::new(0, false);
+        let mut filtering   = Filtering::default();
+        let mut statuses    = vec![player.status];
+        let mut released    = None;
+
+        hydra::start(&mut hydra_state).unwrap();
+
+        for _ in 0..400 {
+            hydra::update(&mut hydra_state);
+
+            let note_was_on = player.zgicabra.note.on;
+
+            play(&mut player, &mut filtering, 0, hydra_state.status[0], hydra::wands(&hydra_state, 0), None, hydra_state.timedelta);
+
+            if player.status != *statuses.last().unwrap() {
+                statuses.push(player.status);
+
+                if player.status == Status::WaitingForControllers {
+                    released = Some((note_was_on, player.delta_events.clone(), player.zgicabra.note.on));
+                }
+            }
+
+            player.history.push(player.zgicabra.clone());
+            player.delta_events.clear();
+        }
+
+        assert_eq!(statuses, [Status::NoBase, Status::Ready, Status::OneWand, Status::Ready, Status::WaitingForControllers, Status::Ready]);
+
+        let (note_was_on, deltas, note_on) = released.unwrap();
+        assert!(note_was_on);
+        assert!(deltas.iter().any(|delta| matches!(delta, DeltaEvent::NoteEnd(_))));
+        assert!(!note_on);
+    }
+
+    // Two players on the same note: the first to let go mustn't cut the other off
+    #[test]
+    fn combined_notes_last_while_anyone_holds_them () {
+        fn hold (players: &mut [Player], notes: [Option<u8>; 2]) -> Vec<DeltaEvent> {
+            for (player, note) in players.iter_mut().zip(notes) {
+                player.history.push(player.zgicabra.clone());
+                player.delta_events.clear();
+
+                match (player.zgicabra.note.on, note) {
+                    (false, Some(note)) => player.delta_events.push(DeltaEvent::NoteStart(note)),
+                    (true,  None)       => player.delta_events.push(DeltaEvent::NoteEnd(player.zgicabra.note.current)),
+                    _ => {},
+                }
+
+                player.zgicabra.note.on      = note.is_some();
+                player.zgicabra.note.current = note.unwrap_or(player.zgicabra.note.current);
+            }
+            combined_deltas(players)
+        }
+
+        let mut players = vec![Player::new(0, false), Player::new(0, false)];
+
+        assert_eq!(hold(&mut players, [Some(60), None]),     [DeltaEvent::NoteStart(60)]);
+        assert_eq!(hold(&mut players, [Some(60), Some(60)]), []);
+        assert_eq!(hold(&mut players, [None,     Some(60)]), []);
+        assert_eq!(hold(&mut players, [None,     Some(62)]), [DeltaEvent::NoteEnd(60), DeltaEvent::NoteStart(62)]);
+        assert_eq!(hold(&mut players, [None,     None]),     [DeltaEvent::NoteEnd(62)]);
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::HISTORY_WINDOW;
+use crate::hydra;
+use crate::session::SessionFrame;
+
+
+//
+// Motion Filters
+//
+// The Hydra's positions are good to about a millimetre, which is fine to play
+// from but turns into noise as soon as it's differentiated: a raw finite
+// difference of a finite difference of a finite difference (jerk) is mostly
+// jitter. Each wand's position goes through one of these on the way in, which
+// gives back a smoothed position along with its derivatives:
+//
+//   average  - the mean of this frame and the last, derivatives by finite
+//              difference. What we've always done, the least lag, and still
+//              the default: the rest are opt-in with --filter.
+//   one-euro - Casiez et al.'s adaptive low-pass: heavy smoothing when the wand
+//              is still, opening up as it speeds up, so fast gestures don't lag.
+//   kalman   - constant-acceleration Kalman filter per axis. Estimates velocity
+//              and acceleration directly rather than differencing.
+//   savgol   - Savitzky-Golay: fits a polynomial over the last few frames and
+//              reads position and every derivative off it at the newest frame.
+//
+// Positions go in and come out in millimetres, as the Hydra reports them. Time is
+// in seconds, and the derivatives come out in metres per second (squared, cubed)
+// so they mean something physical. Parameters are in seconds and hertz, as the
+// literature has them, and millimetres.
+//
+// A step with no time since the last (or none that makes sense) changes nothing,
+// and a reading or result that isn't finite is thrown away rather than allowed to
+// poison everything after it.
+//
+
+const MM_PER_M: f64 = 1000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Average,
+    OneEuro,
+    Kalman,
+    SavitzkyGolay,
+}
+
+pub const KINDS: [Kind; 4] = [Kind::Average, Kind::OneEuro, Kind::Kalman, Kind::SavitzkyGolay];
+
+impl Kind {
+    pub fn name (self) -> &'static str {
+        match self {
+            Kind::Average       => "average",
+            Kind::OneEuro       => "one-euro",
+            Kind::Kalman        => "kalman",
+            Kind::SavitzkyGolay => "savgol",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Kind, String> {
+        KINDS.iter().copied().find(|kind| kind.name() == s).ok_or(format!("unknown filter '{}'", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub kind:              Kind,
+    pub min_cutoff:        f32,     // one-euro: Hz when still
+    pub beta:              f32,     // one-euro: extra Hz per mm/s of speed
+    pub d_cutoff:          f32,     // one-euro: Hz for the speed estimate
+    pub process_noise:     f32,     // kalman: jerk variance, (mm/s³)²
+    pub measurement_noise: f32,     // kalman: position variance, mm²
+    pub window:            usize,   // savgol: frames in the fit
+    pub degree:            usize,   // savgol: polynomial degree, 1 to 3
+}
+
+impl Default for Settings {
+    fn default () -> Settings {
+        Settings {
+            kind:              Kind::Average,
+            min_cutoff:        1.0,
+            beta:              0.1,
+            d_cutoff:          1.0,
+            process_noise:     1.0e8,
+            measurement_noise: 1.0,
+            window:            HISTORY_WINDOW,
+            degree:            3,
+        }
+    }
+}
+
+impl Settings {
+    // Set one parameter from a `name=value` pair
+    pub fn set (&mut self, pair: &str) -> Result<(), String> {
+        let (name, value) = pair.split_once('=').ok_or(format!("expected name=value, got '{}'", pair))?;
+        let number: f32 = value.parse().map_err(|_| format!("bad value for {}: '{}'", name, value))?;
+
+        match name {
+            "min_cutoff"        => self.min_cutoff = number,
+            "beta"              => self.beta = number,
+            "d_cutoff"          => self.d_cutoff = number,
+            "process_noise"     => self.process_noise = number,
+            "measurement_noise" => self.measurement_noise = number,
+            "window"            => self.window = (number as usize).max(2),
+            "degree"            => self.degree = (number as usize).clamp(1, 3),
+            _ => return Err(format!("unknown filter parameter '{}'", name)),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Motion {
+    pub pos:  [f32; 3],
+    pub vel:  [f32; 3],
+    pub acc:  [f32; 3],
+    pub jerk: [f32; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub settings: Settings,
+    last:         Motion,
+    primed:       bool,
+    one_euro:     [OneEuroAxis; 3],
+    kalman:       [KalmanAxis; 3],
+    samples:      VecDeque<(f64, [f32; 3])>,   // savgol: (time in s, position)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct OneEuroAxis {
+    x:   f32,   // Filtered
+    dx:  f32,   // Filtered speed, only used to open up the cutoff
+    raw: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct KalmanAxis {
+    x: [f64; 3],        // position, velocity, acceleration, in mm and seconds
+    p: [[f64; 3]; 3],
+}
+
+impl Filter {
+    pub fn new (settings: Settings) -> Filter {
+        Filter {
+            settings,
+            last:     Motion::default(),
+            primed:   false,
+            one_euro: [OneEuroAxis::default(); 3],
+            kalman:   [KalmanAxis::default(); 3],
+            samples:  VecDeque::with_capacity(settings.window),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default () -> Filter {
+        Filter::new(Settings::default())
+    }
+}
+
+
+//
+// Module Functions
+//
+
+// Take in one raw position, `dt` seconds after the last
+pub fn step (filter: &mut Filter, raw: [f32; 3], dt: f32) -> Motion {
+    if dt <= 0.0 || !dt.is_finite() || !all_finite(&raw) {
+        return filter.last;
+    }
+
+    let motion = match filter.settings.kind {
+        Kind::Average       => average(filter, raw, dt),
+        Kind::OneEuro       => one_euro(filter, raw, dt),
+        Kind::Kalman        => kalman(filter, raw, dt),
+        Kind::SavitzkyGolay => savitzky_golay(filter, raw, dt),
+    };
+
+    // Something blew up: start over from the next reading, holding still till then
+    if ![motion.pos, motion.vel, motion.acc, motion.jerk].iter().all(all_finite) {
+        let last = filter.last;
+        reset(filter);
+        return Motion { pos: last.pos, ..Motion::default() };
+    }
+
+    filter.last   = motion;
+    filter.primed = true;
+    motion
+}
+
+// Forget the past, eg. when a wand has been away and will come back somewhere else
+pub fn reset (filter: &mut Filter) {
+    *filter = Filter::new(filter.settings);
+}
+
+
+//
+// Filters
+//
+
+fn average (filter: &Filter, raw: [f32; 3], dt: f32) -> Motion {
+    if !filter.primed {
+        return Motion { pos: raw, ..Motion::default() };
+    }
+
+    let last = &filter.last;
+    let pos  = map3(|i| (raw[i] + last.pos[i]) / 2.0);
+    finite_differences(last, pos, dt)
+}
+
+fn one_euro (filter: &mut Filter, raw: [f32; 3], dt: f32) -> Motion {
+    let settings = filter.settings;
+
+    if !filter.primed {
+        for (axis, x) in filter.one_euro.iter_mut().zip(raw) {
+            *axis = OneEuroAxis { x, dx: 0.0, raw: x };
+        }
+    }
+
+    for (axis, x) in filter.one_euro.iter_mut().zip(raw) {
+        let dx = (x - axis.raw) / dt;
+        axis.dx  = lerp(axis.dx, dx, smoothing(settings.d_cutoff, dt));
+        axis.raw = x;
+
+        let cutoff = settings.min_cutoff + settings.beta * axis.dx.abs();
+        axis.x = lerp(axis.x, x, smoothing(cutoff, dt));
+    }
+
+    let pos = map3(|i| filter.one_euro[i].x);
+
+    if !filter.primed {
+        return Motion { pos, ..Motion::default() };
+    }
+
+    finite_differences(&filter.last, pos, dt)
+}
+
+fn kalman (filter: &mut Filter, raw: [f32; 3], dt: f32) -> Motion {
+    let q = filter.settings.process_noise as f64;
+    let r = filter.settings.measurement_noise as f64;
+    let t = dt as f64;
+
+    if !filter.primed {
+        for (axis, x) in filter.kalman.iter_mut().zip(raw) {
+            *axis = KalmanAxis {
+                x: [x as f64, 0.0, 0.0],
+                p: [[r, 0.0, 0.0], [0.0, 1.0e6, 0.0], [0.0, 0.0, 1.0e8]],
+            };
+        }
+    }
+
+    let f = [[1.0, t, t * t / 2.0], [0.0, 1.0, t], [0.0, 0.0, 1.0]];
+    let g = [t * t * t / 6.0, t * t / 2.0, t];
+
+    for (axis, z) in filter.kalman.iter_mut().zip(raw) {
+
+        // Predict
+        let x = mat_vec(&f, &axis.x);
+        let mut p = mat_mul(&mat_mul(&f, &axis.p), &transpose(&f));
+        for (row, g_row) in p.iter_mut().zip(g) {
+            for (cell, g_col) in row.iter_mut().zip(g) {
+                *cell += q * g_row * g_col;
+            }
+        }
+
+        // Update with the measured position
+        let innovation = z as f64 - x[0];
+        let s = p[0][0] + r;
+        let k = [p[0][0] / s, p[1][0] / s, p[2][0] / s];
+
+        axis.x = [0, 1, 2].map(|i| x[i] + k[i] * innovation);
+        axis.p = [0, 1, 2].map(|i| [0, 1, 2].map(|j| p[i][j] - k[i] * p[0][j]));
+    }
+
+    let pos = map3(|i| filter.kalman[i].x[0] as f32);
+    let vel = map3(|i| (filter.kalman[i].x[1] / MM_PER_M) as f32);
+    let acc = map3(|i| (filter.kalman[i].x[2] / MM_PER_M) as f32);
+
+    Motion { pos, vel, acc, jerk: derivative(acc, filter.last.acc, dt) }
+}
+
+// Least-squares fit of a polynomial in time over the window, evaluated at the
+// newest frame. Frames needn't be evenly spaced.
+fn savitzky_golay (filter: &mut Filter, raw: [f32; 3], dt: f32) -> Motion {
+    let time = filter.samples.back().map(|(time, _)| time + dt as f64).unwrap_or(0.0);
+
+    if filter.samples.len() >= filter.settings.window {
+        filter.samples.pop_front();
+    }
+    filter.samples.push_back((time, raw));
+
+    let degree = filter.settings.degree.min(filter.samples.len() - 1);
+
+    let fits = [0, 1, 2].map(|axis| {
+        let points: Vec<(f64, f64)> = filter.samples.iter().map(|(t, p)| (t - time, p[axis] as f64)).collect();
+        polyfit(&points, degree)
+    });
+
+    Motion {
+        pos:  map3(|i| fits[i][0] as f32),
+        vel:  map3(|i| (fits[i][1] / MM_PER_M) as f32),
+        acc:  map3(|i| (2.0 * fits[i][2] / MM_PER_M) as f32),
+        jerk: map3(|i| (6.0 * fits[i][3] / MM_PER_M) as f32),
+    }
+}
+
+
+//
+// Benchmark
+//
+// Runs every filter over the wands in a recorded session and reports what each
+// costs in lag and what it buys in smoothness. There's no ground truth to compare
+// against, so:
+//
+//   lag    - how far behind the raw positions the filtered ones run, as the
+//            shift that lines them up best
+//   wobble - RMS distance from the raw positions after removing that lag, which
+//            is mostly the sensor noise being filtered out
+//   jerk   - RMS jerk magnitude. Hands don't really jerk much, so less is better.
+//
+
+const MAX_LAG_FRAMES: usize = 20;
+
+pub struct Report {
+    pub kind:    Kind,
+    pub lag_ms:  f32,
+    pub wobble:  f32,
+    pub jerk:    f32,
+}
+
+impl fmt::Display for Report {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:10} lag {:6.1} ms   wobble {:6.3} mm   jerk {:10.3} m/s³", self.kind.name(), self.lag_ms, self.wobble, self.jerk)
+    }
+}
+
+pub fn bench (frames: &[SessionFrame], settings: Settings) -> Vec<Report> {
+    KINDS.iter().map(|&kind| bench_one(frames, Settings { kind, ..settings })).collect()
+}
+
+fn bench_one (frames: &[SessionFrame], settings: Settings) -> Report {
+    let mut raw_runs:      Vec<Vec<[f32; 3]>> = Vec::new();
+    let mut filtered_runs: Vec<Vec<[f32; 3]>> = Vec::new();
+    let mut jerk_squares = 0.0;
+    let mut jerk_count   = 0;
+    let mut total_dt     = 0.0;
+
+    for slot in 0..hydra::MAX_WANDS {
+        let mut filter   = Filter::new(settings);
+        let mut raw      = Vec::new();
+        let mut filtered = Vec::new();
+
+        for frame in frames {
+            let dt = frame.dt_us as f32 / 1.0e6;
+            total_dt += dt;
+
+            // A dropout ends a run, and the filter starts over when the wand's back
+            let Some(controller) = frame.controllers.get(slot).filter(|c| c.enabled != 0) else {
+                if !raw.is_empty() {
+                    raw_runs.push(std::mem::take(&mut raw));
+                    filtered_runs.push(std::mem::take(&mut filtered));
+                }
+                reset(&mut filter);
+                continue;
+            };
+
+            let motion = step(&mut filter, controller.pos, dt);
+
+            if filtered.len() >= 3 {
+                jerk_squares += motion.jerk.iter().map(|j| j * j).sum::<f32>();
+                jerk_count   += 1;
+            }
+
+            raw.push(controller.pos);
+            filtered.push(motion.pos);
+        }
+
+        if !raw.is_empty() {
+            raw_runs.push(raw);
+            filtered_runs.push(filtered);
+        }
+    }
+
+    let mean_dt = total_dt / (frames.len() * hydra::MAX_WANDS).max(1) as f32;
+
+    // Lag is the shift with the least error
+    let (lag, wobble) = (0..=MAX_LAG_FRAMES)
+        .map(|shift| (shift, rms_error(&raw_runs, &filtered_runs, shift)))
+        .fold((0, f32::INFINITY), |best, (shift, error)| if error < best.1 { (shift, error) } else { best });
+
+    Report {
+        kind:   settings.kind,
+        lag_ms: lag as f32 * mean_dt * 1000.0,
+        wobble,
+        jerk:   (jerk_squares / jerk_count.max(1) as f32).sqrt(),
+    }
+}
+
+// RMS distance between each filtered position and the raw one `shift` frames earlier
+fn rms_error (raw_runs: &[Vec<[f32; 3]>], filtered_runs: &[Vec<[f32; 3]>], shift: usize) -> f32 {
+    let mut squares = 0.0;
+    let mut count   = 0;
+
+    for (raw, filtered) in raw_runs.iter().zip(filtered_runs) {
+        for (past, now) in raw.iter().zip(filtered.iter().skip(shift)) {
+            squares += (0..3).map(|i| (now[i] - past[i]).powi(2)).sum::<f32>();
+            count   += 1;
+        }
+    }
+
+    if count == 0 { f32::INFINITY } else { (squares / count as f32).sqrt() }
+}
+
+
+//
+// Helpers
+//
+
+fn finite_differences (last: &Motion, pos: [f32; 3], dt: f32) -> Motion {
+    let vel  = derivative(pos, last.pos, dt).map(|v| v / MM_PER_M as f32);
+    let acc  = derivative(vel, last.vel, dt);
+    let jerk = derivative(acc, last.acc, dt);
+    Motion { pos, vel, acc, jerk }
+}
+
+fn derivative (a: [f32; 3], b: [f32; 3], dt: f32) -> [f32; 3] {
+    map3(|i| (a[i] - b[i]) / dt)
+}
+
+fn all_finite (v: &[f32; 3]) -> bool {
+    v.iter().all(|x| x.is_finite())
+}
+
+fn map3 (f: impl Fn(usize) -> f32) -> [f32; 3] {
+    [f(0), f(1), f(2)]
+}
+
+fn lerp (from: f32, to: f32, amount: f32) -> f32 {
+    from + (to - from) * amount
+}
+
+// How far a first-order low-pass at `cutoff` Hz moves in `seconds`
+fn smoothing (cutoff: f32, seconds: f32) -> f32 {
+    let tau = 1.0 / (2.0 * std::f32::consts::PI * cutoff.max(f32::EPSILON));
+    1.0 / (1.0 + tau / seconds)
+}
+
+// Coefficients, lowest power first, of the least-squares polynomial through
+// `points`. Always four long, with anything above `degree` left at zero.
+fn polyfit (points: &[(f64, f64)], degree: usize) -> [f64; 4] {
+    let n = degree + 1;
+    let mut a = [[0.0; 5]; 4];   // Normal equations, right-hand side in the last column
+
+    for &(t, y) in points {
+        let powers = [1.0, t, t * t, t * t * t];
+        for (row, p_row) in a.iter_mut().zip(powers).take(n) {
+            for (cell, p_col) in row.iter_mut().zip(powers).take(n) {
+                *cell += p_row * p_col;
+            }
+            row[4] += p_row * y;
+        }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+        a.swap(col, pivot);
+
+        let pivot_row = a[col];
+        if pivot_row[col].abs() < 1e-12 {
+            continue;
+        }
+
+        for (ix, row) in a.iter_mut().enumerate().take(n) {
+            if ix != col {
+                let factor = row[col] / pivot_row[col];
+                for (cell, p) in row.iter_mut().zip(pivot_row) {
+                    *cell -= factor * p;
+                }
+            }
+        }
+    }
+
+    let mut coefficients = [0.0; 4];
+    for (ix, (coefficient, row)) in coefficients.iter_mut().zip(a).enumerate().take(n) {
+        if row[ix].abs() >= 1e-12 {
+            *coefficient = row[4] / row[ix];
+        }
+    }
+    coefficients
+}
+
+fn mat_vec (m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+fn mat_mul (a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j]))
+}
+
+fn transpose (m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| m[j][i]))
+}
+
+
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::fixtures;
+
+    #[test]
+    fn polyfit_recovers_a_cubic () {
+        let cubic  = [2.0, 3.0, -5.0, 7.0];
+        let points: Vec<(f64, f64)> = [-0.1, -0.07, -0.05, -0.031, -0.02, -0.01, 0.0].iter()
+            .map(|&t| (t, cubic[0] + cubic[1] * t + cubic[2] * t * t + cubic[3] * t * t * t))
+            .collect();
+
+        for (fit, expected) in polyfit(&points, 3).iter().zip(cubic) {
+            assert!((fit - expected).abs() < 1e-6, "{} instead of {}", fit, expected);
+        }
+    }
+
+    #[test]
+    fn kalman_converges_on_constant_velocity () {
+        let mut filter = Filter::new(Settings { kind: Kind::Kalman, ..Settings::default() });
+        let mut motion = Motion::default();
+
+        // 1 mm/ms is 1 m/s
+        for tick in 0..200 {
+            motion = step(&mut filter, [tick as f32 * 10.0, 50.0, -300.0], 0.01);
+        }
+
+        assert!((motion.pos[0] - 1990.0).abs() < 0.5, "pos {:?}", motion.pos);
+        assert!((motion.vel[0] - 1.0).abs() < 0.01, "vel {:?}", motion.vel);
+        assert!(motion.vel[1].abs() < 0.01 && motion.vel[2].abs() < 0.01, "vel {:?}", motion.vel);
+        assert!(motion.acc.iter().all(|a| a.abs() < 0.1), "acc {:?}", motion.acc);
+    }
+
+    // Fitting the newest frame doesn't lag, averaging with the last lags a little,
+    // and one-euro smooths slow movement hardest
+    #[test]
+    fn bench_orders_filters_by_lag () {
+        let reports = bench(&fixtures::performance(600), Settings::default());
+        let lag = |kind: Kind| reports.iter().find(|report| report.kind == kind).unwrap().lag_ms;
+
+        assert!(lag(Kind::SavitzkyGolay) <= lag(Kind::Average), "{:?}", reports.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+        assert!(lag(Kind::Average) < lag(Kind::OneEuro), "{:?}", reports.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn step_ignores_no_time_and_bad_readings () {
+        let mut filter = Filter::new(Settings::default());
+        let first = step(&mut filter, [1.0, 2.0, 3.0], 0.01);
+
+        assert_eq!(step(&mut filter, [5.0, 5.0, 5.0], 0.0), first);
+        assert_eq!(step(&mut filter, [5.0, 5.0, 5.0], f32::NAN), first);
+        assert_eq!(step(&mut filter, [f32::NAN, 5.0, 5.0], 0.01), first);
+        assert_eq!(step(&mut filter, [5.0, f32::INFINITY, 5.0], 0.01), first);
+        assert_eq!(filter.last, first);
+    }
+
+    #[test]
+    fn step_holds_still_and_starts_over_when_it_blows_up () {
+        let mut filter = Filter::new(Settings::default());
+        step(&mut filter, [1.0, 2.0, 3.0], 0.01);
+        step(&mut filter, [1.0, 2.0, 3.0], 0.01);
+
+        // Finite, but too far to differentiate
+        let held = step(&mut filter, [f32::MAX, 2.0, 3.0], 0.01);
+        assert_eq!(held, Motion { pos: [1.0, 2.0, 3.0], ..Motion::default() });
+
+        // Fresh from the next reading
+        let next = step(&mut filter, [4.0, 5.0, 6.0], 0.01);
+        assert_eq!(next, Motion { pos: [4.0, 5.0, 6.0], ..Motion::default() });
+    }
+
+    #[test]
+    fn every_kind_reports_metres_per_second () {
+        for kind in KINDS {
+            let mut filter = Filter::new(Settings { kind, ..Settings::default() });
+            let mut motion = Motion::default();
+
+            // 1 mm/ms along x
+            for tick in 0..300 {
+                motion = step(&mut filter, [tick as f32 * 10.0, 0.0, 0.0], 0.01);
+            }
+
+            assert!((motion.vel[0] - 1.0).abs() < 0.01, "{} vel {:?}", kind.name(), motion.vel);
+            assert!(motion.acc.iter().all(|a| a.abs() < 0.1), "{} acc {:?}", kind.name(), motion.acc);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+
+//
+// Frame
+//
+// A grid of character cells that the UI draws into instead of straight to the
+// terminal. Text can carry SGR colour escapes (drawille and textplots both emit
+// them); those are peeled off into a per-cell style so two frames can be compared
+// cell by cell.
+//
+// Coordinates are 1-based to match termion::cursor::Goto.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    ch:    char,
+    style: u16,
+}
+
+const BLANK: Cell = Cell { ch: ' ', style: 0 };
+
+#[derive(Clone)]
+pub struct Frame {
+    pub w:  u16,
+    pub h:  u16,
+    cells:  Vec<Cell>,
+    styles: Vec<String>,   // Style 0 is always plain
+}
+
+impl Frame {
+    pub fn new (w: u16, h: u16) -> Frame {
+        Frame {
+            w,
+            h,
+            cells:  vec![BLANK; w as usize * h as usize],
+            styles: vec![String::new()],
+        }
+    }
+}
+
+pub fn clear (frame: &mut Frame, w: u16, h: u16) {
+    frame.w = w;
+    frame.h = h;
+    frame.cells.clear();
+    frame.cells.resize(w as usize * h as usize, BLANK);
+    frame.styles.truncate(1);
+}
+
+pub fn put (frame: &mut Frame, x: u16, y: u16, text: &str) {
+    let mut style = String::new();
+    let mut col   = x;
+    let mut row   = y;
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x1b' if chars.peek() == Some(&'[') => {
+                let mut sequence = String::from("\x1b");
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if c.is_ascii_alphabetic() { break; }
+                }
+
+                if sequence.ends_with('m') {
+                    if sequence == "\x1b[0m" || sequence == "\x1b[m" {
+                        style.clear();
+                    } else {
+                        style.push_str(&sequence);
+                    }
+                }
+            },
+
+            '\n' => {
+                col  = x;
+                row += 1;
+            },
+
+            _ => {
+                if col >= 1 && row >= 1 && col <= frame.w && row <= frame.h {
+                    let style = intern(frame, &style);
+                    let ix    = (row - 1) as usize * frame.w as usize + (col - 1) as usize;
+                    frame.cells[ix] = Cell { ch, style };
+                }
+                col += 1;
+            },
+        }
+    }
+}
+
+fn intern (frame: &mut Frame, style: &str) -> u16 {
+    match frame.styles.iter().position(|s| s == style) {
+        Some(ix) => ix as u16,
+        None => {
+            frame.styles.push(style.to_string());
+            (frame.styles.len() - 1) as u16
+        }
+    }
+}
+
+
+//
+// Renderer
+//
+// Remembers what's on screen and writes only the cells that changed, as one
+// buffered write per frame. Anything that might have disturbed the screen behind
+// our back (resizing, switching layouts) should call `invalidate`.
+//
+
+pub struct Renderer {
+    previous: Option<Frame>,
+    out:      String,
+}
+
+impl Renderer {
+    pub fn new () -> Renderer {
+        Renderer {
+            previous: None,
+            out:      String::new(),
+        }
+    }
+}
+
+pub fn invalidate (renderer: &mut Renderer) {
+    renderer.previous = None;
+}
+
+pub fn present (renderer: &mut Renderer, frame: &Frame) -> io::Result<()> {
+    let out = &mut renderer.out;
+    out.clear();
+
+    let previous = renderer.previous.as_ref().filter(|p| p.w == frame.w && p.h == frame.h);
+
+    if previous.is_none() {
+        let _ = write!(out, "{}", termion::clear::All);
+    }
+
+    let mut cursor: Option<(u16, u16)> = None;
+    let mut current_style = "";
+
+    for y in 1..=frame.h {
+        for x in 1..=frame.w {
+            let ix    = (y - 1) as usize * frame.w as usize + (x - 1) as usize;
+            let cell  = frame.cells[ix];
+            let style = frame.styles[cell.style as usize].as_str();
+
+            if let Some(previous) = previous {
+                let old = previous.cells[ix];
+                if old.ch == cell.ch && previous.styles[old.style as usize] == style {
+                    continue;
+                }
+            }
+
+            if cursor != Some((x, y)) {
+                let _ = write!(out, "{}", termion::cursor::Goto(x, y));
+            }
+
+            if style != current_style {
+                let _ = write!(out, "{}{}", termion::style::Reset, style);
+                current_style = style;
+            }
+
+            out.push(cell.ch);
+            cursor = Some((x + 1, y));
+        }
+    }
+
+    if !current_style.is_empty() {
+        let _ = write!(out, "{}", termion::style::Reset);
+    }
+
+    if !out.is_empty() {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()?;
+    }
+
+    renderer.previous = Some(frame.clone());
+    Ok(())
+}
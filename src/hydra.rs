@@ -1,8 +1,14 @@
 
+use std::fmt;
 use std::time::{Instant,Duration};
 use std::thread::sleep;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use libc::{c_float, c_int, c_uint, c_uchar, c_ushort};
+use rgb::RGB8;
+use serde::{Serialize, Deserialize};
+
+use crate::session::{self, SessionFrame};
 
 pub const LEFT_HAND:  c_uchar = 1;
 pub const RIGHT_HAND: c_uchar = 2;
@@ -15,6 +21,12 @@ pub const BUTTON_2        : c_uint = 0b001000000;
 pub const BUTTON_3        : c_uint = 0b000001000;
 pub const BUTTON_4        : c_uint = 0b000010000;
 
+const MAX_CONTROLLERS: usize = 4;   // Per base, as far as the SDK is concerned
+
+// One player per base, each with a left and a right wand
+pub const MAX_PLAYERS: usize = 2;
+pub const MAX_WANDS:   usize = MAX_PLAYERS * 2;
+
 
 //
 // ControllerFrame
@@ -22,15 +34,8 @@ pub const BUTTON_4        : c_uint = 0b000010000;
 // One frame of all data from the hydra formatted according to Sixense API
 //
 
-#[link(name="sixense_x64")]
-extern {
-    pub fn sixenseInit();
-    pub fn sixenseExit();
-    pub fn sixenseGetNewestData(which: c_int, data: *mut ControllerFrame);
-}
-
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct ControllerFrame {
     pub pos: [c_float; 3],
     pub rot_mat: [[c_float; 3]; 3],
@@ -42,8 +47,8 @@ pub struct ControllerFrame {
     pub rot_quat: [c_float; 4],
     pub firmware_revision: c_ushort,
     pub hardware_revision: c_ushort,
-    pub packet_type: c_uchar,
-    pub magnetic_frequency: c_uchar,
+    pub packet_type: c_ushort,
+    pub magnetic_frequency: c_ushort,
     pub enabled: c_int,
     pub controller_index: c_int,
     pub is_docked: c_uchar,
@@ -51,6 +56,9 @@ pub struct ControllerFrame {
     pub hemi_tracking_enabled: c_uchar,
 }
 
+// Must match sixenseControllerData in sdk/sixense.h byte for byte, since the SDK
+// writes straight into it. The tests check it against the header itself.
+
 impl ControllerFrame {
     pub fn new() -> ControllerFrame {
         ControllerFrame {
@@ -87,71 +95,489 @@ impl Default for ControllerFrame {
 //
 // Manages a block of memory in which we can record and manipulate incoming hydra data
 //
+// Each base belongs to one player, and their wands live in `controllers` as
+// [ p1 left, p1 right, p2 left, p2 right ].
+//
+// Bases and wands can come and go at any time. `status` says, per player, whether
+// this tick has fresh data for both hands; a wand that didn't report is marked
+// disabled in its frame, so recorded sessions capture the dropout too.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Status {
+    NoBase,
+    WaitingForControllers,
+    OneWand,   // Only one of the pair, enough for single-wand play
+    Ready,
+}
+
+impl Status {
+    pub fn playable (self) -> bool {
+        matches!(self, Status::OneWand | Status::Ready)
+    }
+}
+
+// Where frames come from. The simulated backend replays a recorded session on a
+// loop, dropouts and all, so the rest of the system can be run without hardware.
+pub enum Backend {
+    Sixense,
+    Simulated { frames: Vec<SessionFrame>, cursor: usize },
+}
 
 pub struct HydraState {
     pub initialised: bool,
+    pub status:      [ Status; MAX_PLAYERS ],
+    pub backend:     Backend,
     pub timestamp:   Instant,
     pub timedelta:   Duration,
     pub temp_frame:  ControllerFrame,
-    pub controllers: [ ControllerFrame; 2 ],
+    pub controllers: [ ControllerFrame; MAX_WANDS ],
+    pub hemisphere:  [ bool; MAX_WANDS ],   // Asked the SDK to track which side of the base each wand is on
 }
 
 impl HydraState {
     pub fn new() -> HydraState {
         HydraState {
             initialised: false,
+            status: [ Status::NoBase; MAX_PLAYERS ],
+            backend: Backend::Sixense,
             timestamp: Instant::now(),
             timedelta: Duration::from_millis(0),
             temp_frame: ControllerFrame::new(),
-            controllers: [ ControllerFrame::new(), ControllerFrame::new() ],
+            controllers: [ ControllerFrame::new(); MAX_WANDS ],
+            hemisphere: [ false; MAX_WANDS ],
+        }
+    }
+
+    pub fn simulated(frames: Vec<SessionFrame>) -> HydraState {
+        HydraState {
+            backend: Backend::Simulated { frames, cursor: 0 },
+            ..HydraState::new()
         }
     }
 }
 
 
 
+//
+// Sixense API
+//
+// Everything in sdk/sixense.h, wrapped so nothing outside this section needs
+// unsafe. Calls that report success or failure come back as a Result; the rest
+// return their value as is.
+//
+
+// sixenseAllControllerData, checked against the header like ControllerFrame
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct AllControllerFrames {
+    controllers: [ ControllerFrame; MAX_CONTROLLERS ],
+}
+
+#[link(name="sixense_x64")]
+extern {
+    fn sixenseInit() -> c_int;
+    fn sixenseExit() -> c_int;
+
+    fn sixenseGetMaxBases() -> c_int;
+    fn sixenseSetActiveBase(i: c_int) -> c_int;
+    fn sixenseIsBaseConnected(i: c_int) -> c_int;
+
+    fn sixenseGetMaxControllers() -> c_int;
+    fn sixenseIsControllerEnabled(which: c_int) -> c_int;
+    fn sixenseGetNumActiveControllers() -> c_int;
+
+    fn sixenseGetHistorySize() -> c_int;
+
+    fn sixenseGetData(which: c_int, index_back: c_int, data: *mut ControllerFrame) -> c_int;
+    fn sixenseGetAllData(index_back: c_int, data: *mut AllControllerFrames) -> c_int;
+    fn sixenseGetNewestData(which: c_int, data: *mut ControllerFrame) -> c_int;
+    fn sixenseGetAllNewestData(data: *mut AllControllerFrames) -> c_int;
+
+    fn sixenseSetHemisphereTrackingMode(which: c_int, state: c_int) -> c_int;
+    fn sixenseGetHemisphereTrackingMode(which: c_int, state: *mut c_int) -> c_int;
+    fn sixenseAutoEnableHemisphereTracking(which: c_int) -> c_int;
+
+    fn sixenseSetHighPriorityBindingEnabled(on_or_off: c_int) -> c_int;
+    fn sixenseGetHighPriorityBindingEnabled(on_or_off: *mut c_int) -> c_int;
+
+    fn sixenseTriggerVibration(controller_id: c_int, duration_100ms: c_int, pattern_id: c_int) -> c_int;
+
+    fn sixenseSetFilterEnabled(on_or_off: c_int) -> c_int;
+    fn sixenseGetFilterEnabled(on_or_off: *mut c_int) -> c_int;
+    fn sixenseSetFilterParams(near_range: c_float, near_val: c_float, far_range: c_float, far_val: c_float) -> c_int;
+    fn sixenseGetFilterParams(near_range: *mut c_float, near_val: *mut c_float, far_range: *mut c_float, far_val: *mut c_float) -> c_int;
+
+    fn sixenseSetBaseColor(red: c_uchar, green: c_uchar, blue: c_uchar) -> c_int;
+    fn sixenseGetBaseColor(red: *mut c_uchar, green: *mut c_uchar, blue: *mut c_uchar) -> c_int;
+}
+
+const SIXENSE_SUCCESS: c_int = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SixenseError {
+    pub call: &'static str,
+}
+
+impl fmt::Display for SixenseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sixense: {} failed", self.call)
+    }
+}
+
+impl std::error::Error for SixenseError {}
+
+// The SDK's own smoothing. Positions are in millimetres from the base; the
+// filter blends from `near_val` at `near_range` to `far_val` at `far_range`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterParams {
+    pub near_range: f32,
+    pub near_val:   f32,
+    pub far_range:  f32,
+    pub far_val:    f32,
+}
+
+fn check (call: &'static str, result: c_int) -> Result<(), SixenseError> {
+    if result == SIXENSE_SUCCESS { Ok(()) } else { Err(SixenseError { call }) }
+}
+
+pub fn init () -> Result<(), SixenseError> {
+    check("init", unsafe { sixenseInit() })
+}
+
+pub fn exit () -> Result<(), SixenseError> {
+    check("exit", unsafe { sixenseExit() })
+}
+
+pub fn max_bases () -> usize {
+    unsafe { sixenseGetMaxBases() }.max(0) as usize
+}
+
+pub fn set_active_base (base: usize) -> Result<(), SixenseError> {
+    check("set_active_base", unsafe { sixenseSetActiveBase(base as c_int) })
+}
+
+pub fn is_base_connected (base: usize) -> bool {
+    unsafe { sixenseIsBaseConnected(base as c_int) != 0 }
+}
+
+pub fn max_controllers () -> usize {
+    unsafe { sixenseGetMaxControllers() }.max(0) as usize
+}
+
+pub fn is_controller_enabled (which: usize) -> bool {
+    unsafe { sixenseIsControllerEnabled(which as c_int) != 0 }
+}
+
+pub fn active_controllers () -> usize {
+    unsafe { sixenseGetNumActiveControllers() }.max(0) as usize
+}
+
+// How many frames back `data` and `all_data` can reach
+pub fn history_size () -> usize {
+    unsafe { sixenseGetHistorySize() }.max(0) as usize
+}
+
+pub fn data (which: usize, index_back: usize) -> Result<ControllerFrame, SixenseError> {
+    let mut frame = ControllerFrame::new();
+    check("get_data", unsafe { sixenseGetData(which as c_int, index_back as c_int, &mut frame) })?;
+    Ok(frame)
+}
+
+pub fn all_data (index_back: usize) -> Result<[ ControllerFrame; MAX_CONTROLLERS ], SixenseError> {
+    let mut all = AllControllerFrames { controllers: [ ControllerFrame::new(); MAX_CONTROLLERS ] };
+    check("get_all_data", unsafe { sixenseGetAllData(index_back as c_int, &mut all) })?;
+    Ok(all.controllers)
+}
+
+pub fn newest_data (which: usize) -> Result<ControllerFrame, SixenseError> {
+    let mut frame = ControllerFrame::new();
+    check("get_newest_data", unsafe { sixenseGetNewestData(which as c_int, &mut frame) })?;
+    Ok(frame)
+}
+
+pub fn all_newest_data () -> Result<[ ControllerFrame; MAX_CONTROLLERS ], SixenseError> {
+    let mut all = AllControllerFrames { controllers: [ ControllerFrame::new(); MAX_CONTROLLERS ] };
+    check("get_all_newest_data", unsafe { sixenseGetAllNewestData(&mut all) })?;
+    Ok(all.controllers)
+}
+
+// Up to `history_size` frames for one controller, newest first
+pub fn history (which: usize) -> Result<Vec<ControllerFrame>, SixenseError> {
+    (0..history_size()).map(|index_back| data(which, index_back)).collect()
+}
+
+pub fn set_hemisphere_tracking (which: usize, enabled: bool) -> Result<(), SixenseError> {
+    check("set_hemisphere_tracking", unsafe { sixenseSetHemisphereTrackingMode(which as c_int, enabled as c_int) })
+}
+
+pub fn hemisphere_tracking (which: usize) -> Result<bool, SixenseError> {
+    let mut state = 0;
+    check("get_hemisphere_tracking", unsafe { sixenseGetHemisphereTrackingMode(which as c_int, &mut state) })?;
+    Ok(state != 0)
+}
+
+// Only gives the right answer while the wand is pointing at the base, eg. docked
+pub fn auto_enable_hemisphere_tracking (which: usize) -> Result<(), SixenseError> {
+    check("auto_enable_hemisphere_tracking", unsafe { sixenseAutoEnableHemisphereTracking(which as c_int) })
+}
+
+pub fn set_high_priority_binding (enabled: bool) -> Result<(), SixenseError> {
+    check("set_high_priority_binding", unsafe { sixenseSetHighPriorityBindingEnabled(enabled as c_int) })
+}
+
+pub fn high_priority_binding () -> Result<bool, SixenseError> {
+    let mut state = 0;
+    check("get_high_priority_binding", unsafe { sixenseGetHighPriorityBindingEnabled(&mut state) })?;
+    Ok(state != 0)
+}
+
+// Duration is rounded to the SDK's 100ms steps
+pub fn trigger_vibration (which: usize, duration: Duration, pattern: i32) -> Result<(), SixenseError> {
+    let steps = (duration.as_millis() / 100) as c_int;
+    check("trigger_vibration", unsafe { sixenseTriggerVibration(which as c_int, steps, pattern) })
+}
+
+pub fn set_filter_enabled (enabled: bool) -> Result<(), SixenseError> {
+    check("set_filter_enabled", unsafe { sixenseSetFilterEnabled(enabled as c_int) })
+}
+
+pub fn filter_enabled () -> Result<bool, SixenseError> {
+    let mut state = 0;
+    check("get_filter_enabled", unsafe { sixenseGetFilterEnabled(&mut state) })?;
+    Ok(state != 0)
+}
+
+pub fn set_filter_params (params: FilterParams) -> Result<(), SixenseError> {
+    check("set_filter_params", unsafe {
+        sixenseSetFilterParams(params.near_range, params.near_val, params.far_range, params.far_val)
+    })
+}
+
+pub fn filter_params () -> Result<FilterParams, SixenseError> {
+    let mut params = FilterParams { near_range: 0.0, near_val: 0.0, far_range: 0.0, far_val: 0.0 };
+    check("get_filter_params", unsafe {
+        sixenseGetFilterParams(&mut params.near_range, &mut params.near_val, &mut params.far_range, &mut params.far_val)
+    })?;
+    Ok(params)
+}
+
+pub fn set_base_color (color: RGB8) -> Result<(), SixenseError> {
+    check("set_base_color", unsafe { sixenseSetBaseColor(color.r, color.g, color.b) })
+}
+
+pub fn base_color () -> Result<RGB8, SixenseError> {
+    let mut color = RGB8 { r: 0, g: 0, b: 0 };
+    check("get_base_color", unsafe { sixenseGetBaseColor(&mut color.r, &mut color.g, &mut color.b) })?;
+    Ok(color)
+}
+
+
+
 //
 // Functions
-// TODO: Learn what the correct thing is to do with the unsafes here
 //
 
-pub fn start (state: &mut HydraState) {
-    print!("Hydra::start - init connection... ");
-    unsafe { sixenseInit(); }
-    state.initialised = true;
-    println!("✅");
+// Whether the SDK is up, kept outside HydraState so a panic hook can still shut it down
+static SDK_RUNNING: AtomicBool = AtomicBool::new(false);
 
-    print!("Hydra::start - awaiting first frame...");
-    while state.temp_frame.which_hand == 0 {
-        read_frame(0, &mut state.temp_frame);
-        sleep(Duration::from_millis(10));
+// Doesn't wait for the wands: until both are reporting, `status` says so and the
+// caller can hold off.
+pub fn start (state: &mut HydraState) -> Result<(), SixenseError> {
+    if let Backend::Simulated { .. } = &state.backend {
+        state.initialised = true;
+        return Ok(());
     }
-    println!("✅");
+
+    init()?;
+    SDK_RUNNING.store(true, Ordering::SeqCst);
+    state.initialised = true;
+    Ok(())
 }
 
 pub fn stop (state: &mut HydraState) {
-    println!("Hydra::stop - closing down... ");
-    unsafe { sixenseExit(); }
+    release();
     state.initialised = false;
-    println!("Hydra::stop - done.");
+}
+
+// Shut down the SDK if it's running. Safe to call more than once, and from anywhere.
+pub fn release () {
+    if SDK_RUNNING.swap(false, Ordering::SeqCst) {
+        let _ = exit();
+    }
 }
 
 pub fn update (state: &mut HydraState) {
+    state.timedelta = Instant::now().duration_since(state.timestamp);
+    state.timestamp = Instant::now();
 
-    read_frame(0, &mut state.temp_frame);
-    let hand = (state.temp_frame.which_hand - 1) as usize;
-    state.controllers[hand] = state.temp_frame;
+    let mut bases = [false; MAX_PLAYERS];
 
-    read_frame(1, &mut state.temp_frame);
-    let hand = (state.temp_frame.which_hand - 1) as usize;
-    state.controllers[hand] = state.temp_frame;
+    match &mut state.backend {
+        Backend::Sixense => {
+            let mut seen = [false; MAX_WANDS];
 
-    state.timedelta = Instant::now().duration_since(state.timestamp);
-    state.timestamp = Instant::now();
+            for (base, connected) in bases.iter_mut().enumerate() {
+                if !is_base_connected(base) || set_active_base(base).is_err() {
+                    continue;
+                }
+                *connected = true;
+
+                for which in 0..MAX_CONTROLLERS {
+                    if !is_controller_enabled(which) || read_frame(which, &mut state.temp_frame).is_err() {
+                        continue;
+                    }
+
+                    if let Some(hand) = hand_index(state.temp_frame.which_hand) {
+                        state.controllers[base * 2 + hand] = state.temp_frame;
+                        seen[base * 2 + hand] = true;
+
+                        // Without hemisphere tracking a wand behind the base reads as
+                        // in front of it. Auto-enable only picks the right side while
+                        // the wand points at the base, so it's asked once per wand
+                        // and only while docked (the SDK does the same by itself on
+                        // docking). Until then, and for flips that get through
+                        // anyway, tracking.rs catches them.
+                        let docked = state.temp_frame.is_docked != 0;
+
+                        if docked && state.temp_frame.hemi_tracking_enabled == 0 && !state.hemisphere[base * 2 + hand] {
+                            state.hemisphere[base * 2 + hand] = auto_enable_hemisphere_tracking(which).is_ok();
+                        }
+                    }
+                }
+            }
+
+            for ((frame, asked), seen) in state.controllers.iter_mut().zip(state.hemisphere.iter_mut()).zip(seen) {
+                if !seen {
+                    frame.enabled = 0;
+                    *asked = false;
+                }
+            }
+        },
+
+        Backend::Simulated { frames, cursor } => {
+            if !frames.is_empty() {
+                let frame = frames[*cursor % frames.len()].clone();
+                *cursor += 1;
+
+                session::apply_frame(&frame, state);
+                bases = [true; MAX_PLAYERS];   // Recordings only know about wands
+            }
+        },
+    }
+
+    for (player, status) in state.status.iter_mut().enumerate() {
+        let (left, right) = (&state.controllers[player * 2], &state.controllers[player * 2 + 1]);
+
+        *status = match (bases[player], wand_ready(left, 0), wand_ready(right, 1)) {
+            (false, _, _)        => Status::NoBase,
+            (true, true, true)   => Status::Ready,
+            (true, false, false) => Status::WaitingForControllers,
+            (true, _, _)         => Status::OneWand,
+        };
+    }
 }
 
-pub fn read_frame (which: i32, frame_data: &mut ControllerFrame) {
-    unsafe { sixenseGetNewestData(which, frame_data); }
+// The left and right wand of one player
+pub fn wands (state: &HydraState, player: usize) -> (&ControllerFrame, &ControllerFrame) {
+    (&state.controllers[player * 2], &state.controllers[player * 2 + 1])
 }
 
+// Whether a wand is live and has settled on the hand its slot expects
+pub fn wand_ready (frame: &ControllerFrame, hand: usize) -> bool {
+    frame.enabled != 0 && hand_index(frame.which_hand) == Some(hand)
+}
+
+// Which side of a player's pair a frame belongs in. Wands report 0 until the SDK
+// has worked out which hand they're in.
+pub fn hand_index (which_hand: c_uchar) -> Option<usize> {
+    match which_hand {
+        LEFT_HAND  => Some(0),
+        RIGHT_HAND => Some(1),
+        _ => None,
+    }
+}
+
+pub fn read_frame (which: usize, frame_data: &mut ControllerFrame) -> Result<(), SixenseError> {
+    *frame_data = newest_data(which)?;
+    Ok(())
+}
+
+
+
 
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{align_of, offset_of, size_of};
+    use std::process::Command;
+
+    // Compiles a probe against sdk/sixense.h with the system C compiler, so the
+    // layout comes from the header rather than from anyone's arithmetic
+    fn layout_from_header (lines: &[(&str, String)]) -> Vec<(String, usize)> {
+        let dir    = std::env::temp_dir().join(format!("zgicabra-layout-{}", std::process::id()));
+        let source = dir.join("probe.c");
+        let binary = dir.join("probe");
+        let sdk    = concat!(env!("CARGO_MANIFEST_DIR"), "/sdk");
+
+        let prints: String = lines.iter()
+            .map(|(name, expr)| format!("    printf(\"%s %zu\\n\", \"{}\", (size_t) ({}));\n", name, expr))
+            .collect();
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&source, format!(
+            "#include <stdio.h>\n#include <stddef.h>\n#include <stdalign.h>\n#include \"sixense.h\"\n\nint main (void) {{\n{}    return 0;\n}}\n",
+            prints)).unwrap();
+
+        let compiled = Command::new("cc").arg("-I").arg(sdk).arg(&source).arg("-o").arg(&binary)
+            .status().expect("layout check needs a C compiler on the path");
+        assert!(compiled.success(), "couldn't compile the sixense.h probe");
+
+        let output = Command::new(&binary).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        String::from_utf8(output.stdout).unwrap().lines()
+            .map(|line| {
+                let (name, value) = line.split_once(' ').unwrap();
+                (name.to_string(), value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn layout_matches_sixense_h () {
+        macro_rules! field {
+            ($field:ident) => {
+                (stringify!($field), offset_of!(ControllerFrame, $field), format!("offsetof(sixenseControllerData, {})", stringify!($field)))
+            };
+        }
+
+        let fields = [
+            field!(pos), field!(rot_mat), field!(joystick_x), field!(joystick_y),
+            field!(trigger), field!(buttons), field!(sequence_number), field!(rot_quat),
+            field!(firmware_revision), field!(hardware_revision), field!(packet_type),
+            field!(magnetic_frequency), field!(enabled), field!(controller_index),
+            field!(is_docked), field!(which_hand), field!(hemi_tracking_enabled),
+        ];
+
+        let mut expected = vec![
+            ("size",            size_of::<ControllerFrame>(),     "sizeof(sixenseControllerData)".to_string()),
+            ("align",           align_of::<ControllerFrame>(),    "alignof(sixenseControllerData)".to_string()),
+            ("all_size",        size_of::<AllControllerFrames>(), "sizeof(sixenseAllControllerData)".to_string()),
+            ("max_controllers", MAX_CONTROLLERS,                  "SIXENSE_MAX_CONTROLLERS".to_string()),
+        ];
+        expected.extend(fields);
+
+        let probe: Vec<(&str, String)> = expected.iter().map(|(name, _, expr)| (*name, expr.clone())).collect();
+        let header = layout_from_header(&probe);
+
+        assert_eq!(header.len(), expected.len());
+        for ((name, rust, _), (_, c)) in expected.iter().zip(header) {
+            assert_eq!(*rust, c, "{} differs from sixense.h", name);
+        }
+    }
+}
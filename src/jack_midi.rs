@@ -0,0 +1,172 @@
+
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::{Duration, Instant};
+
+use jack::{AsyncClient, Client, ClientOptions, ClientStatus, ClosureProcessHandler, Control, Frames, MidiOut, NotificationHandler, ProcessScope, RawMidi};
+use serde_json::json;
+
+use crate::logging;
+use crate::midi::MidiError;
+use crate::midi_event::*;
+
+
+//
+// JACK MIDI
+//
+// Alternative MIDI output that places every event at a precise frame inside the
+// JACK cycle instead of firing it whenever the main loop gets around to it.
+//
+// Each event is stamped with the moment its sensor data was captured
+// (HydraState.timestamp) and scheduled `latency` after that. As long as the loop
+// gets its work done within the latency budget, the gap between any two gestures
+// is reproduced exactly at the synth. Events that arrive too late for their slot
+// are sent at the start of the next cycle.
+//
+// If more than MAX_PENDING events back up, new ones are dropped and counted,
+// except for note-offs and panics: those push out something that matters less,
+// since losing one leaves a note hanging.
+//
+
+const PORT_NAME:   &str  = "midi_out";
+const MAX_PENDING: usize = 1024;
+
+struct Scheduled {
+    frame: Frames,
+    bytes: [u8; 3],
+}
+
+type Process = Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send>;
+type Handler = ClosureProcessHandler<Process>;
+
+// Notices when the JACK server goes away under us
+struct Notifications {
+    alive: Arc<AtomicBool>,
+}
+
+impl NotificationHandler for Notifications {
+    fn shutdown (&mut self, _status: ClientStatus, _reason: &str) {
+        self.alive.store(false, Ordering::Relaxed);
+    }
+}
+
+pub struct JackMidi {
+    pub latency: Duration,
+    client:      AsyncClient<Notifications, Handler>,
+    sender:      Sender<Scheduled>,
+    alive:       Arc<AtomicBool>,
+    dropped:     Arc<AtomicU64>,   // Counted in the process callback
+    reported:    u64,              // Logged from the control thread
+}
+
+
+//
+// Module Functions
+//
+
+pub fn start (name: &str, latency: Duration) -> Result<JackMidi, Box<dyn Error>> {
+    let (client, _status) = Client::new(name, ClientOptions::NO_START_SERVER)?;
+    let mut port = client.register_port(PORT_NAME, MidiOut)?;
+
+    let (sender, receiver): (Sender<Scheduled>, Receiver<Scheduled>) = channel();
+    let mut pending: Vec<Scheduled> = Vec::with_capacity(MAX_PENDING);
+
+    let dropped = Arc::new(AtomicU64::new(0));
+    let overflow = dropped.clone();
+
+    let process: Process = Box::new(move |_, ps| {
+        let cycle_start = ps.last_frame_time();
+        let cycle_len   = ps.n_frames();
+
+        while let Ok(event) = receiver.try_recv() {
+            if pending.len() < MAX_PENDING {
+                pending.push(event);
+                continue;
+            }
+
+            overflow.fetch_add(1, Ordering::Relaxed);
+
+            if essential(&event.bytes) {
+                match pending.iter().position(|queued| !essential(&queued.bytes)) {
+                    Some(ix) => pending[ix] = event,
+                    None     => pending.push(event),
+                }
+            }
+        }
+
+        let mut writer      = port.writer(ps);
+        let mut last_offset = 0;
+
+        pending.retain(|event| {
+            let offset = event.frame.wrapping_sub(cycle_start) as i32;
+
+            if offset >= cycle_len as i32 {
+                return true;  // Belongs to a future cycle
+            }
+
+            // JACK wants events in order within a cycle, so late ones queue up behind the last write
+            last_offset = offset.max(last_offset);
+
+            let _ = writer.write(&RawMidi { time: last_offset as Frames, bytes: &event.bytes });
+            false
+        });
+
+        Control::Continue
+    });
+
+    let alive  = Arc::new(AtomicBool::new(true));
+    let client = client.activate_async(Notifications { alive: alive.clone() }, ClosureProcessHandler::new(process))?;
+
+    Ok(JackMidi { latency, client, sender, alive, dropped, reported: 0 })
+}
+
+pub fn is_alive (jack_midi: &JackMidi) -> bool {
+    jack_midi.alive.load(Ordering::Relaxed)
+}
+
+pub fn dispatch (jack_midi: &mut JackMidi, midi_events: &[MidiEvent], captured_at: Instant) -> Result<(), MidiError> {
+    if !is_alive(jack_midi) {
+        return Err(MidiError::Disconnected("JACK server shut down".to_string()));
+    }
+
+    let dropped = jack_midi.dropped.load(Ordering::Relaxed);
+    if dropped > jack_midi.reported {
+        logging::warn("jack_overflow", json!({ "dropped": dropped - jack_midi.reported, "total": dropped }));
+        jack_midi.reported = dropped;
+    }
+
+    let client      = jack_midi.client.as_client();
+    let sample_rate = client.sample_rate() as f32;
+
+    let since_capture = captured_at.elapsed().as_secs_f32();
+    let target_delay  = jack_midi.latency.as_secs_f32() - since_capture;
+    let frame         = client.frame_time().wrapping_add((target_delay.max(0.0) * sample_rate) as Frames);
+
+    for event in midi_events {
+        jack_midi.sender.send(Scheduled { frame, bytes: [event.msg, event.msb, event.lsb] })
+            .map_err(|_| MidiError::Disconnected("JACK process callback is gone".to_string()))?;
+    }
+
+    Ok(())
+}
+
+pub fn close (jack_midi: JackMidi) {
+    let _ = jack_midi.client.deactivate();
+}
+
+
+//
+// Helpers
+//
+
+// Events that mustn't be lost: anything that lets go of a note
+fn essential (bytes: &[u8; 3]) -> bool {
+    match bytes[0] & 0xF0 {
+        MSG_NOTE_OFF       => true,
+        MSG_NOTE_ON        => bytes[2] == 0,
+        MSG_CONTROL_CHANGE => bytes[1] == CC_MIDI_PANIC || bytes[1] == CC_SILENCE,
+        _ => false,
+    }
+}
@@ -0,0 +1,60 @@
+
+use termion::event::Key;
+
+use crate::zgicabra::DeltaEvent;
+use crate::ui::Layout;
+
+
+//
+// Keymap
+//
+// Keyboard shortcuts for things you'd otherwise need the wands (or a restart)
+// for. Actions either stay in the UI thread, or become a Command for the control
+// thread to apply.
+//
+
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    Quit,
+    Help,
+    NextLayout,
+    NextPlayer,
+    Calibrate,
+    CalibrateNext,
+    Layout(Layout),
+    Control(fn () -> DeltaEvent),
+}
+
+pub struct Binding {
+    pub key:         Key,
+    pub label:       &'static str,
+    pub description: &'static str,
+    pub action:      Action,
+}
+
+pub const KEYMAP: &[Binding] = &[
+    Binding { key: Key::Char('q'),  label: "q",     description: "Quit",                 action: Action::Quit },
+    Binding { key: Key::Esc,        label: "Esc",   description: "Quit",                 action: Action::Quit },
+    Binding { key: Key::Ctrl('c'),  label: "^C",    description: "Quit",                 action: Action::Quit },
+    Binding { key: Key::Char(' '),  label: "Space", description: "Panic: all notes off", action: Action::Control(DeltaEvent::Panic) },
+    Binding { key: Key::Char('?'),  label: "?",     description: "Show/hide this help",  action: Action::Help },
+    Binding { key: Key::Char('1'),  label: "1",     description: "Performance view",     action: Action::Layout(Layout::Performance) },
+    Binding { key: Key::Char('2'),  label: "2",     description: "Debug view",           action: Action::Layout(Layout::Debug) },
+    Binding { key: Key::Char('3'),  label: "3",     description: "Kinematics view",      action: Action::Layout(Layout::Kinematics) },
+    Binding { key: Key::Char('4'),  label: "4",     description: "MIDI monitor view",    action: Action::Layout(Layout::MidiMonitor) },
+    Binding { key: Key::Char('\t'), label: "Tab",   description: "Next view",            action: Action::NextLayout },
+    Binding { key: Key::Char('p'),  label: "p",     description: "Focus next player",    action: Action::NextPlayer },
+    Binding { key: Key::Char('c'),  label: "c",     description: "Calibrate/cancel",     action: Action::Calibrate },
+    Binding { key: Key::Char('\n'), label: "Enter", description: "Next calibration step", action: Action::CalibrateNext },
+    Binding { key: Key::Right,      label: "→",     description: "Transpose up",         action: Action::Control(DeltaEvent::TuneUp) },
+    Binding { key: Key::Left,       label: "←",     description: "Transpose down",       action: Action::Control(DeltaEvent::TuneDown) },
+    Binding { key: Key::Up,         label: "↑",     description: "Octave up",            action: Action::Control(DeltaEvent::OctaveUp) },
+    Binding { key: Key::Down,       label: "↓",     description: "Octave down",          action: Action::Control(DeltaEvent::OctaveDown) },
+    Binding { key: Key::Char(']'),  label: "]",     description: "Next voice",           action: Action::Control(DeltaEvent::NextVoice) },
+    Binding { key: Key::Char('['),  label: "[",     description: "Previous voice",       action: Action::Control(DeltaEvent::PrevVoice) },
+    Binding { key: Key::Char('s'),  label: "s",     description: "Cycle scales",         action: Action::Control(DeltaEvent::NextScale) },
+];
+
+pub fn action_for (key: Key) -> Option<Action> {
+    KEYMAP.iter().find(|binding| binding.key == key).map(|binding| binding.action)
+}
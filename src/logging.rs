@@ -0,0 +1,93 @@
+
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde_json::{json, Map, Value};
+
+use crate::tools::time_now;
+
+
+//
+// Logging
+//
+// Structured logs for running without a terminal. Every entry is one JSON object
+// on its own line of stderr:
+//
+//   { "time": 12.34, "level": "info", "event": "midi_connected", ...fields }
+//
+// `time` is seconds since startup, `event` is a fixed name that's safe to grep or
+// filter on, and anything else is specific to that event. Logging is off unless a
+// level is set, since stray output would tear through the TUI.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error = 1,
+    Warn  = 2,
+    Info  = 3,
+    Debug = 4,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+
+impl Level {
+    pub fn name (self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn  => "warn",
+            Level::Info  => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Level, String> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn"  => Ok(Level::Warn),
+            "info"  => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("unknown log level '{}'", s)),
+        }
+    }
+}
+
+
+//
+// Module Functions
+//
+
+pub fn init (level: Option<Level>) {
+    LEVEL.store(level.map(|l| l as u8).unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn enabled (level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log (level: Level, event: &str, fields: Value) {
+    if !enabled(level) {
+        return;
+    }
+
+    let mut entry = Map::new();
+    entry.insert("time".into(),  json!(time_now()));
+    entry.insert("level".into(), json!(level.name()));
+    entry.insert("event".into(), json!(event));
+
+    if let Value::Object(fields) = fields {
+        entry.extend(fields);
+    }
+
+    let line = Value::Object(entry).to_string();
+    let _ = writeln!(io::stderr().lock(), "{}", line);
+}
+
+pub fn error (event: &str, fields: Value) { log(Level::Error, event, fields); }
+pub fn warn  (event: &str, fields: Value) { log(Level::Warn,  event, fields); }
+pub fn info  (event: &str, fields: Value) { log(Level::Info,  event, fields); }
+pub fn debug (event: &str, fields: Value) { log(Level::Debug, event, fields); }
@@ -1,10 +1,15 @@
 #![allow(dead_code, unused_imports, unused_variables)]
 
-use std::io::{Read, stdout};
+use std::collections::VecDeque;
+use std::io::{Read, Write, stdout};
+use std::panic;
+use std::sync::{Arc, mpsc};
+use std::sync::mpsc::Receiver;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::thread::JoinHandle;
 use std::thread::sleep;
-use std::time::Duration;
-
-use midir::{MidiOutput, MidiOutputConnection};
+use std::time::{Duration, Instant};
 
 mod tools;
 mod hydra;
@@ -12,16 +17,39 @@ mod zgicabra;
 mod midi;
 mod midi_event;
 mod ui;
-
-use hydra::HydraState;
-use zgicabra::{Zgicabra, DeltaEvent};
+mod frame;
+mod config;
+mod telemetry;
+mod session;
+mod control;
+mod scheduler;
+mod keys;
+mod calibration;
+mod orientation;
+mod filter;
+mod modulation;
+mod space;
+mod tracking;
+mod logging;
+#[cfg(feature = "jack")]
+mod jack_midi;
+#[cfg(feature = "synth")]
+mod synth;
+
+use termion::input::TermRead;
+use serde_json::json;
+
+use config::Config;
+use control::{Command, Shared};
+use keys::Action;
+use zgicabra::DeltaEvent;
 use midi_event::MidiEvent;
 
 
 pub const HISTORY_WINDOW: usize = 10;
 
-const REFRESH_MS: Duration = Duration::from_millis(10);
-const MIDI_DEVICE_NAME: &str = "Zgicabra";
+const MIDI_LOG_LENGTH: usize = 200;
+const HEADLESS_POLL: Duration = Duration::from_millis(100);
 
 
 
@@ -45,76 +73,215 @@ const MIDI_DEVICE_NAME: &str = "Zgicabra";
 
 fn main() {
 
-    print!("{}{}{}", termion::clear::All, termion::cursor::Hide, termion::cursor::Goto(1,1));
-    println!("█║▌▌║│▌█║▌▌║║║▌║║▌▌│▌█│║▌▌│║█▌║▌│ zgicabra ▌▌│║▌║▌█║▌║▌║█║▌║│▌█║║▌▌║║║▌║║█▌│\n");
+    let config = Config::from_args();
 
+    logging::init(config.log_level);
 
-    //
-    // Setup Phase
-    //
+    if let Some((session_path, wav_path)) = config.render.as_ref() {
+        render(session_path, wav_path, &config);
+        return;
+    }
+
+    if let Some(session_path) = config.bench_filters.as_ref() {
+        bench_filters(session_path, &config);
+        return;
+    }
+
+    let default_panic = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        ui::restore_terminal();
+        hydra::release();
+        default_panic(info);
+    }));
 
-    print!("Establishing MIDI connection... ");
+    let shared = Arc::new(Shared::new());
+    let (commands, command_receiver) = mpsc::channel::<Command>();
 
-    let output         = MidiOutput::new(MIDI_DEVICE_NAME).unwrap();
-    let out_port       = output.ports()[0].clone();
-    let port_name      = output.port_name(&out_port).unwrap_or("Unknown".to_string());
-    let mut connection = output.connect(&out_port, "midir-test").unwrap();
+    if config.headless {
+        headless(&config, shared, command_receiver);
+        return;
+    }
 
-    println!("✅");
+    let mut terminal = ui::Terminal::enter().unwrap();
 
-    let mut hydra_state = HydraState::new();
-    let mut zgicabra    = Zgicabra::new();
-    let mut history:      Vec<Zgicabra>   = Vec::with_capacity(HISTORY_WINDOW);
-    let mut midi_events:  Vec<MidiEvent>  = Vec::new();
-    let mut delta_events: Vec<DeltaEvent> = Vec::new();
+    println!("█║▌▌║│▌█║▌▌║║║▌║║▌▌│▌█│║▌▌│║█▌║▌│ zgicabra ▌▌│║▌║▌█║▌║▌║█║▌║│▌█║║▌▌║║║▌║║█▌│\n");
 
+    let control_thread = spawn_control(&config, shared.clone(), command_receiver);
 
+
+    //
+    // UI Loop
     //
-    // Hydra Setup
+    // Keys are read without blocking. Raw mode waits for the first frame so the
+    // setup messages above still print normally. Each frame is drawn off-screen
+    // and only the cells that changed are sent to the terminal.
     //
 
-    hydra::start(&mut hydra_state);
+    let mut keys = termion::async_stdin().keys();
+    let mut layout    = ui::Layout::Performance;
+    let mut show_help = false;
+    let mut focus     = 0;
+    let mut screen    = ui::screen_rect();
+    let mut frame     = frame::Frame::new(screen.w, screen.h);
+    let mut renderer  = frame::Renderer::new();
+    let mut midi_log: VecDeque<MidiEvent> = VecDeque::with_capacity(MIDI_LOG_LENGTH);
+    let mut drawn_first_frame = false;
+
+    while shared.running.load(Ordering::Relaxed) {
+        let frame_start = Instant::now();
+
+        if ui::interrupted() || control_thread.is_finished() {
+            shared.running.store(false, Ordering::Relaxed);
+            break;
+        }
 
-    sleep(Duration::from_millis(1000));
+        for key in keys.by_ref().flatten() {
+            match keys::action_for(key) {
+                Some(Action::Quit)           => shared.running.store(false, Ordering::Relaxed),
+                Some(Action::Help)           => show_help = !show_help,
+                Some(Action::NextLayout)     => layout = layout.next(),
+                Some(Action::NextPlayer)     => focus = (focus + 1) % config.players,
+                Some(Action::Calibrate)      => { let _ = commands.send(Command::Calibrate(focus)); },
+                Some(Action::CalibrateNext)  => { let _ = commands.send(Command::CalibrateNext); },
+                Some(Action::Layout(chosen)) => layout = chosen,
+                Some(Action::Control(delta)) => { let _ = commands.send(Command::Delta(delta())); },
+                None => continue,
+            }
+
+            frame::invalidate(&mut renderer);
+        }
 
-    history.push(zgicabra.clone()); // Fill first frame to allow initial derivatives
+        let snapshot = shared.latest.lock().unwrap().take();
 
-    print!("{}", termion::clear::All);
+        if let Some(snapshot) = snapshot {
+            if !drawn_first_frame {
+                terminal.raw_mode();
+                frame::invalidate(&mut renderer);
+                drawn_first_frame = true;
+            }
 
-    loop {
-        hydra::update(&mut hydra_state);
-        zgicabra::update(&mut zgicabra, &history.last().unwrap(), &hydra_state, &mut delta_events);
+            if ui::screen_rect() != screen {
+                screen = ui::screen_rect();
+                frame::invalidate(&mut renderer);
+            }
 
-        midi::update(&zgicabra, &delta_events, &mut midi_events);
-        midi::dispatch(&midi_events, &mut connection);
+            for event in snapshot.midi_events.iter() {
+                if midi_log.len() >= MIDI_LOG_LENGTH { midi_log.pop_front(); }
+                midi_log.push_back(*event);
+            }
 
-        ui::draw_all(&zgicabra, &history);
-        //ui::draw_events(&delta_events, &midi_events);
-        //ui::draw_note_state(&zgicabra.note, &zgicabra.signal);
-        //ui::draw_graph(&history);
+            frame::clear(&mut frame, screen.w, screen.h);
+            ui::draw_layout(&mut frame, layout, &snapshot, focus, &midi_log, config.control_period);
 
-        midi_events.clear();
-        delta_events.clear();
+            if show_help { ui::draw_help(&mut frame); }
 
-        if history.len() >= HISTORY_WINDOW {
-            history.remove(0);
+            let _ = frame::present(&mut renderer, &frame);
         }
-        history.push(zgicabra.clone());
 
-        sleep(REFRESH_MS);
+        sleep(config.ui_period.saturating_sub(frame_start.elapsed()));
+    }
 
-        if std::io::stdin().bytes().next().and_then(|result| result.ok()).is_some() {
-            break;
+    drop(terminal);
+    shared.ui_closed.store(true, Ordering::Relaxed);
+
+    if control_thread.join().is_err() {
+        hydra::release();
+        println!("Control thread panicked, hardware released.");
+    }
+
+}
+
+
+//
+// Control Thread
+//
+// Sets up hardware and outputs, then runs sensors -> MIDI until told to stop
+//
+
+fn spawn_control (config: &Config, shared: Arc<Shared>, commands: Receiver<Command>) -> JoinHandle<()> {
+    let config = config.clone();
+
+    thread::spawn(move || {
+        let control = control::start(&config);
+        let control = control::run(control, &shared, commands);
+
+        // Let the UI hand the terminal back before we print the shutdown log
+        while !shared.ui_closed.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(10));
         }
+
+        control::stop(control);
+    })
+}
+
+
+//
+// Headless
+//
+// No terminal, no keyboard: the control thread runs until it's signalled to stop,
+// and everything worth knowing goes to the structured log.
+//
+
+fn headless (config: &Config, shared: Arc<Shared>, commands: Receiver<Command>) {
+    ui::catch_interrupts();
+    shared.ui_closed.store(true, Ordering::Relaxed);
+
+    let control_thread = spawn_control(config, shared.clone(), commands);
+
+    while !ui::interrupted() && !control_thread.is_finished() {
+        // Nobody's drawing snapshots, so collect them to stop events piling up
+        shared.latest.lock().unwrap().take();
+        sleep(HEADLESS_POLL);
     }
 
-    hydra::stop(&mut hydra_state);
+    shared.running.store(false, Ordering::Relaxed);
 
-    print!("Closing connection... ");
+    if control_thread.join().is_err() {
+        hydra::release();
+        logging::error("control_panicked", json!({}));
+    }
+}
 
-    midi::close(connection);
 
-    println!("ok");
+//
+// Offline Render
+//
+
+#[cfg(feature = "synth")]
+fn render (session_path: &str, wav_path: &str, config: &Config) {
+    print!("Rendering {} to {}... ", session_path, wav_path);
+
+    let zgicabra = zgicabra::Zgicabra {
+        calibration:    calibration::lookup(&calibration::load(&config.calibration).unwrap_or_default(), 0),
+        rotation_order: config.rotation_order,
+        mirrored:       config.mirrored[0],
+        ..zgicabra::Zgicabra::new()
+    };
+
+    match session::render(session_path, wav_path, config.seed, zgicabra, config.filter, &config.mappings) {
+        Ok(frames) => println!("✅ ({} frames)", frames),
+        Err(err)   => println!("❌ {}", err),
+    }
+}
 
+#[cfg(not(feature = "synth"))]
+fn render (session_path: &str, wav_path: &str, config: &Config) {
+    println!("Rendering needs the built-in synth, rebuild with `--features synth`");
 }
 
+
+//
+// Filter Benchmark
+//
+
+fn bench_filters (session_path: &str, config: &Config) {
+    match session::load(session_path) {
+        Ok(frames) => {
+            println!("{} frames from {}, {:?}\n", frames.len(), session_path, config.filter);
+            for report in filter::bench(&frames, config.filter) {
+                println!("{}", report);
+            }
+        },
+        Err(err) => println!("❌ {}", err),
+    }
+}
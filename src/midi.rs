@@ -1,33 +1,114 @@
 
-use std::time::Duration;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
 use std::thread::sleep;
 
-use midir::{MidiOutput, MidiOutputConnection};
+use midir::{MidiOutput, MidiOutputConnection, SendError};
 
 use crate::zgicabra::{Zgicabra,DeltaEvent};
 use crate::midi_event::{MidiEvent};
+use crate::modulation;
+use crate::modulation::Modulation;
 
 
 // Custom MIDI CCs
 
-const CC_CUTOFF:       u8 = 0x20;
-const CC_FUZZ:         u8 = 0x21;
-const CC_THUMP:        u8 = 0x22;
-const CC_VELOCITY:     u8 = 0x23;
-const CC_ACCELERATION: u8 = 0x24;
-const CC_JERK:         u8 = 0x25;
-const CC_BIGNESS:      u8 = 0x26;
-const CC_WIDTH:        u8 = 0x27;
+pub const CC_CUTOFF:       u8 = 0x20;
+pub const CC_FUZZ:         u8 = 0x21;
+pub const CC_THUMP:        u8 = 0x22;
+pub const CC_VELOCITY:     u8 = 0x23;
+pub const CC_ACCELERATION: u8 = 0x24;
+pub const CC_JERK:         u8 = 0x25;
+pub const CC_BIGNESS:      u8 = 0x26;
+pub const CC_WIDTH:        u8 = 0x27;
 
 type Conn = MidiOutputConnection;
 
+const CLIENT_PORT_NAME: &str = "midir-test";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+
+//
+// Errors
+//
+
+#[derive(Debug)]
+pub enum MidiError {
+    NoPorts,                   // Nothing to connect to
+    Connect(String),           // A port was there but wouldn't open
+    InvalidData { failed: usize, total: usize, reason: &'static str },
+    Disconnected(String),      // The port went away; we'll keep trying to get it back
+    NotConnected,              // Events dropped while waiting to reconnect
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiError::NoPorts                               => write!(f, "no MIDI output ports available"),
+            MidiError::Connect(reason)                       => write!(f, "couldn't open MIDI port: {}", reason),
+            MidiError::InvalidData { failed, total, reason } => write!(f, "{} of {} events rejected: {}", failed, total, reason),
+            MidiError::Disconnected(reason)                  => write!(f, "MIDI port lost: {}", reason),
+            MidiError::NotConnected                          => write!(f, "MIDI port not connected"),
+        }
+    }
+}
+
+impl Error for MidiError {}
+
+
+//
+// Port
+//
+// Wherever the MIDI is going. midir talks to the ALSA sequencer and sends on
+// dispatch; JACK schedules each event against its capture time.
+//
+
+pub enum Port {
+    Midir(Conn),
+    #[cfg(feature = "jack")]
+    Jack(crate::jack_midi::JackMidi),
+}
+
+
+//
+// Connection
+//
+// A Port plus enough to find it again. Interfaces get unplugged mid-set, and ALSA
+// won't always tell us on send, so the port list is checked every so often too.
+// Once lost, we retry every RECONNECT_INTERVAL until the same port comes back.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Connected,
+    Lost { attempts: u32 },
+}
+
+pub enum Change {
+    Lost(MidiError),
+    Reconnected,
+}
+
+enum Target {
+    Midir { device_name: String, port_name: String, probe: MidiOutput },
+    #[cfg(feature = "jack")]
+    Jack { device_name: String, latency: Duration },
+}
+
+pub struct Connection {
+    pub status: Status,
+    port:       Option<Port>,
+    target:     Target,
+    last_check: Instant,
+}
 
 
 //
 // Module Functions
 //
 
-pub fn update (zgicabra: &Zgicabra, delta_events: &Vec<DeltaEvent>, midi_events: &mut Vec<MidiEvent>) {
+pub fn update (zgicabra: &Zgicabra, modulation: &mut Modulation, delta_events: &Vec<DeltaEvent>, midi_events: &mut Vec<MidiEvent>) {
 
     // 'Always' events
     midi_events.push(MidiEvent::pitch_bend((zgicabra.note.bend * 8192.0 + 8192.0) as i16));
@@ -35,6 +116,7 @@ pub fn update (zgicabra: &Zgicabra, delta_events: &Vec<DeltaEvent>, midi_events:
     midi_events.push(MidiEvent::control_change(CC_CUTOFF, (zgicabra.signal.filter * 127.0) as u8));
     //midi_events.push(MidiEvent::control_change(CC_FUZZ, (lvl * 127.0) as u8));
     //midi_events.push(MidiEvent::control_change(CC_WIDTH, (lvl * 127.0) as u8));
+    modulation::update(modulation, zgicabra, midi_events);
 
     // Events Deltas
     for delta in delta_events.iter() {
@@ -56,15 +138,126 @@ pub fn update (zgicabra: &Zgicabra, delta_events: &Vec<DeltaEvent>, midi_events:
                 midi_events.push(MidiEvent::note_on(*to, 127));
             },
 
+            DeltaEvent::VoiceChange(voice) => {
+                midi_events.push(MidiEvent::program_change(*voice as u8));
+            },
+
             _ => {}
         }
     }
 }
 
-pub fn dispatch (midi_events: &Vec<MidiEvent>, conn: &mut Conn) {
-    for event in midi_events {
-        let result = conn.send(&[event.msg, event.msb, event.lsb]);
+pub fn connect_midir (device_name: &str) -> Result<Connection, MidiError> {
+    let output = MidiOutput::new(device_name).map_err(|err| MidiError::Connect(err.to_string()))?;
+    let probe  = MidiOutput::new(device_name).map_err(|err| MidiError::Connect(err.to_string()))?;
+
+    let out_port  = output.ports().first().cloned().ok_or(MidiError::NoPorts)?;
+    let port_name = output.port_name(&out_port).map_err(|err| MidiError::Connect(err.to_string()))?;
+    let conn      = output.connect(&out_port, CLIENT_PORT_NAME).map_err(|err| MidiError::Connect(err.to_string()))?;
+
+    Ok(Connection {
+        status:     Status::Connected,
+        port:       Some(Port::Midir(conn)),
+        target:     Target::Midir { device_name: device_name.to_string(), port_name, probe },
+        last_check: Instant::now(),
+    })
+}
+
+#[cfg(feature = "jack")]
+pub fn connect_jack (device_name: &str, latency: Duration) -> Result<Connection, MidiError> {
+    let jack_midi = crate::jack_midi::start(device_name, latency).map_err(|err| MidiError::Connect(err.to_string()))?;
+
+    Ok(Connection {
+        status:     Status::Connected,
+        port:       Some(Port::Jack(jack_midi)),
+        target:     Target::Jack { device_name: device_name.to_string(), latency },
+        last_check: Instant::now(),
+    })
+}
+
+// Sends everything it can. A port that's gone is dropped and left to `maintain` to
+// bring back; bad data only costs the events it was in.
+pub fn dispatch (midi_events: &Vec<MidiEvent>, connection: &mut Connection, captured_at: Instant) -> Result<(), MidiError> {
+    let result = match connection.port.as_mut() {
+        None => return Err(MidiError::NotConnected),
+
+        Some(Port::Midir(conn)) => {
+            let mut failed = 0;
+            let mut first_error = None;
+
+            for event in midi_events {
+                if let Err(err) = conn.send(&[event.msg, event.msb, event.lsb]) {
+                    first_error.get_or_insert(err);
+                    failed += 1;
+                }
+            }
+
+            match first_error {
+                None => Ok(()),
+                Some(SendError::InvalidData(reason)) => Err(MidiError::InvalidData { failed, total: midi_events.len(), reason }),
+                Some(SendError::Other(reason))       => Err(MidiError::Disconnected(reason.to_string())),
+            }
+        },
+
+        #[cfg(feature = "jack")]
+        Some(Port::Jack(jack_midi)) => crate::jack_midi::dispatch(jack_midi, midi_events, captured_at),
+    };
+
+    if let Err(MidiError::Disconnected(_)) = result {
+        lose(connection);
+    }
+
+    result
+}
+
+// Call once per tick. Notices ports that vanished without a send failing, and
+// retries lost ones. After a reconnect the caller should send `restore_events`.
+pub fn maintain (connection: &mut Connection) -> Option<Change> {
+    if connection.last_check.elapsed() < RECONNECT_INTERVAL {
+        return None;
+    }
+    connection.last_check = Instant::now();
+
+    match connection.status {
+        Status::Connected => {
+            if port_present(connection) {
+                None
+            } else {
+                lose(connection);
+                Some(Change::Lost(MidiError::Disconnected("port no longer listed".to_string())))
+            }
+        },
+
+        Status::Lost { attempts } => {
+            match reopen(&connection.target) {
+                Ok(port) => {
+                    connection.port   = Some(port);
+                    connection.status = Status::Connected;
+                    Some(Change::Reconnected)
+                },
+                Err(_) => {
+                    connection.status = Status::Lost { attempts: attempts + 1 };
+                    None
+                },
+            }
+        },
+    }
+}
+
+// Whatever the synth on the other end missed while we were away: silence anything
+// left hanging, then bring back the voice and any note still being held.
+pub fn restore_events (zgicabra: &Zgicabra) -> Vec<MidiEvent> {
+    let mut midi_events = vec![
+        MidiEvent::panic(),
+        MidiEvent::program_change(zgicabra.voice as u8),
+    ];
+
+    if zgicabra.note.on {
+        midi_events.push(MidiEvent::pitch_bend((zgicabra.note.bend * 8192.0 + 8192.0) as i16));
+        midi_events.push(MidiEvent::note_on(zgicabra.note.current, 127));
     }
+
+    midi_events
 }
 
 pub fn clear (midi_events: &mut Vec<MidiEvent>, limit: usize) {
@@ -75,9 +268,65 @@ pub fn clear (midi_events: &mut Vec<MidiEvent>, limit: usize) {
     }
 }
 
-pub fn close (conn: Conn) {
+pub fn close (connection: Connection) {
     // Taking ownership so we can destroy it
-    conn.close();
+    if let Some(port) = connection.port {
+        close_port(port);
+    }
 }
 
 
+//
+// Helpers
+//
+
+fn lose (connection: &mut Connection) {
+    if let Some(port) = connection.port.take() {
+        close_port(port);
+    }
+    connection.status = Status::Lost { attempts: 0 };
+}
+
+fn close_port (port: Port) {
+    match port {
+        Port::Midir(conn) => { conn.close(); },
+
+        #[cfg(feature = "jack")]
+        Port::Jack(jack_midi) => crate::jack_midi::close(jack_midi),
+    }
+}
+
+fn port_present (connection: &Connection) -> bool {
+    match (&connection.target, &connection.port) {
+        (Target::Midir { port_name, probe, .. }, _) => {
+            probe.ports().iter().any(|port| probe.port_name(port).as_ref() == Ok(port_name))
+        },
+
+        #[cfg(feature = "jack")]
+        (Target::Jack { .. }, Some(Port::Jack(jack_midi))) => crate::jack_midi::is_alive(jack_midi),
+
+        #[cfg(feature = "jack")]
+        (Target::Jack { .. }, _) => false,
+    }
+}
+
+fn reopen (target: &Target) -> Result<Port, MidiError> {
+    match target {
+        Target::Midir { device_name, port_name, .. } => {
+            let output = MidiOutput::new(device_name).map_err(|err| MidiError::Connect(err.to_string()))?;
+
+            let out_port = output.ports().into_iter()
+                .find(|port| output.port_name(port).as_ref() == Ok(port_name))
+                .ok_or(MidiError::NoPorts)?;
+
+            let conn = output.connect(&out_port, CLIENT_PORT_NAME).map_err(|err| MidiError::Connect(err.to_string()))?;
+            Ok(Port::Midir(conn))
+        },
+
+        #[cfg(feature = "jack")]
+        Target::Jack { device_name, latency } => {
+            let jack_midi = crate::jack_midi::start(device_name, *latency).map_err(|err| MidiError::Connect(err.to_string()))?;
+            Ok(Port::Jack(jack_midi))
+        },
+    }
+}
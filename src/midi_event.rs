@@ -1,19 +1,21 @@
 
 use std::fmt;
 
+use serde::Serialize;
+
 
 // MIDI Message Types
-const MSG_NOTE_ON:        u8 = 0x90;
-const MSG_NOTE_OFF:       u8 = 0x80;
-const MSG_CONTROL_CHANGE: u8 = 0xB0;
-const MSG_PROGRAM_CHANGE: u8 = 0xC0;
-const MSG_PITCH_BEND:     u8 = 0xE0;
+pub const MSG_NOTE_ON:        u8 = 0x90;
+pub const MSG_NOTE_OFF:       u8 = 0x80;
+pub const MSG_CONTROL_CHANGE: u8 = 0xB0;
+pub const MSG_PROGRAM_CHANGE: u8 = 0xC0;
+pub const MSG_PITCH_BEND:     u8 = 0xE0;
 
 // Default MIDI CCs
-const CC_MOD_WHEEL:       u8 = 0x01;
-const CC_PORTAMENTO_RATE: u8 = 0x05;
-const CC_MIDI_PANIC:      u8 = 0x7B;
-const CC_SILENCE:         u8 = 0x78;
+pub const CC_MOD_WHEEL:       u8 = 0x01;
+pub const CC_PORTAMENTO_RATE: u8 = 0x05;
+pub const CC_MIDI_PANIC:      u8 = 0x7B;
+pub const CC_SILENCE:         u8 = 0x78;
 
 
 //
@@ -22,6 +24,7 @@ const CC_SILENCE:         u8 = 0x78;
 // Dispatch MIDI events with particular parameters
 //
 
+#[derive(Clone, Copy, PartialEq, Serialize)]
 pub struct MidiEvent {
     pub msg: u8,
     pub msb: u8,
@@ -77,11 +80,27 @@ impl MidiEvent {
             lsb: 0
         }
     }
+
+    // Everything above is built on channel 1 (0 here); move it to another
+    pub fn on_channel (self, channel: u8) -> MidiEvent {
+        MidiEvent {
+            msg: (self.msg & 0xF0) | (channel & 0x0F),
+            ..self
+        }
+    }
+
+    pub fn channel (&self) -> u8 {
+        self.msg & 0x0F
+    }
 }
 
 impl fmt::Debug for MidiEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.msg {
+        if self.channel() != 0 {
+            write!(f, "[ch{}] ", self.channel() + 1)?;
+        }
+
+        match self.msg & 0xF0 {
             MSG_NOTE_ON        => write!(f, "Note On: {}@{}", self.msb, self.lsb),
             MSG_NOTE_OFF       => write!(f, "Note Off: {}", self.msb),
             MSG_CONTROL_CHANGE => write!(f, "Control Change: {} {}", self.msb, self.lsb),
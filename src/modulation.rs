@@ -0,0 +1,186 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use crate::midi_event::MidiEvent;
+use crate::zgicabra::Zgicabra;
+
+
+//
+// Modulation
+//
+// Any continuous value the instrument measures can be sent out as a MIDI CC. A
+// mapping names the source, the CC to send it on, and the stretch of the source
+// that sweeps the CC from 0 to 127 (backwards if min is above max):
+//
+//   --map height=30              hands level is the middle of CC 30
+//   --map distance=31:100:500    10cm apart is 0, 50cm is 127
+//
+// Positions are in millimetres and angles in radians, so the default ranges are
+// roughly what two arms can reach.
+//
+// A CC only goes out when its value changes, so a still hand is silent on the
+// wire. Each stream of MIDI keeps its own record of what it last sent.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Level,
+    Bend,
+    Separation,
+    Distance,
+    RelativeX,
+    RelativeY,
+    RelativeZ,
+    Height,
+    Angle,
+    MidpointX,
+    MidpointY,
+    MidpointZ,
+}
+
+pub const SOURCES: [Source; 12] = [
+    Source::Level, Source::Bend, Source::Separation, Source::Distance,
+    Source::RelativeX, Source::RelativeY, Source::RelativeZ, Source::Height,
+    Source::Angle, Source::MidpointX, Source::MidpointY, Source::MidpointZ,
+];
+
+impl Source {
+    pub fn name (self) -> &'static str {
+        match self {
+            Source::Level      => "level",
+            Source::Bend       => "bend",
+            Source::Separation => "separation",
+            Source::Distance   => "distance",
+            Source::RelativeX  => "relative-x",
+            Source::RelativeY  => "relative-y",
+            Source::RelativeZ  => "relative-z",
+            Source::Height     => "height",
+            Source::Angle      => "angle",
+            Source::MidpointX  => "midpoint-x",
+            Source::MidpointY  => "midpoint-y",
+            Source::MidpointZ  => "midpoint-z",
+        }
+    }
+
+    // What sweeps the whole CC unless the mapping says otherwise
+    pub fn default_range (self) -> (f32, f32) {
+        match self {
+            Source::Level      => (0.0, 1.0),
+            Source::Bend       => (-1.0, 1.0),
+            Source::Separation => (0.0, 600.0),
+            Source::Distance   => (0.0, 600.0),
+            Source::Angle      => (0.0, PI),
+            _                  => (-400.0, 400.0),
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Source, String> {
+        SOURCES.iter().copied().find(|source| source.name() == s).ok_or(format!("unknown modulation source '{}'", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    pub source: Source,
+    pub cc:     u8,
+    pub min:    f32,
+    pub max:    f32,
+}
+
+// source=cc, or source=cc:min:max
+impl FromStr for Mapping {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Mapping, String> {
+        let (name, rest) = s.split_once('=').ok_or(format!("expected source=cc, got '{}'", s))?;
+        let source: Source = name.parse()?;
+        let mut parts = rest.split(':');
+
+        let cc = parts.next().and_then(|cc| cc.parse::<u8>().ok()).filter(|cc| *cc < 128)
+            .ok_or(format!("bad CC for {}: '{}'", name, rest))?;
+
+        let (min, max) = match (parts.next(), parts.next()) {
+            (None, _) => source.default_range(),
+            (Some(min), Some(max)) => (
+                min.parse().map_err(|_| format!("bad minimum for {}: '{}'", name, min))?,
+                max.parse().map_err(|_| format!("bad maximum for {}: '{}'", name, max))?,
+            ),
+            (Some(_), None) => return Err(format!("expected source=cc:min:max, got '{}'", s)),
+        };
+
+        Ok(Mapping { source, cc, min, max })
+    }
+}
+
+// The mappings for one stream of MIDI, and the last value each one sent
+#[derive(Debug, Clone)]
+pub struct Modulation {
+    pub mappings: Vec<Mapping>,
+    sent:         Vec<Option<u8>>,
+}
+
+impl Modulation {
+    pub fn new (mappings: &[Mapping]) -> Modulation {
+        Modulation {
+            mappings: mappings.to_vec(),
+            sent:     vec![None; mappings.len()],
+        }
+    }
+}
+
+
+//
+// Module Functions
+//
+
+pub fn value (zgicabra: &Zgicabra, source: Source) -> f32 {
+    let geometry = &zgicabra.geometry;
+
+    match source {
+        Source::Level      => zgicabra.level,
+        Source::Bend       => zgicabra.note.bend,
+        Source::Separation => zgicabra.separation,
+        Source::Distance   => geometry.distance,
+        Source::RelativeX  => geometry.relative[0],
+        Source::RelativeY  => geometry.relative[1],
+        Source::RelativeZ  => geometry.relative[2],
+        Source::Height     => geometry.height,
+        Source::Angle      => geometry.angle,
+        Source::MidpointX  => geometry.midpoint[0],
+        Source::MidpointY  => geometry.midpoint[1],
+        Source::MidpointZ  => geometry.midpoint[2],
+    }
+}
+
+// The source scaled into its range, 0.0 to 1.0
+pub fn amount (zgicabra: &Zgicabra, mapping: &Mapping) -> f32 {
+    let span = mapping.max - mapping.min;
+
+    if span == 0.0 {
+        return 0.0;
+    }
+
+    let amount = (value(zgicabra, mapping.source) - mapping.min) / span;
+    if amount.is_finite() { amount.clamp(0.0, 1.0) } else { 0.0 }
+}
+
+pub fn update (modulation: &mut Modulation, zgicabra: &Zgicabra, midi_events: &mut Vec<MidiEvent>) {
+    for (mapping, sent) in modulation.mappings.iter().zip(modulation.sent.iter_mut()) {
+        let value = (amount(zgicabra, mapping) * 127.0).round() as u8;
+
+        if *sent != Some(value) {
+            midi_events.push(MidiEvent::control_change(mapping.cc, value));
+            *sent = Some(value);
+        }
+    }
+}
+
+// Send everything again next update, eg. when whatever was listening may have
+// lost track
+pub fn forget (modulation: &mut Modulation) {
+    modulation.sent.fill(None);
+}
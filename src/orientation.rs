@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+
+//
+// Orientation
+//
+// The Hydra reports each wand's orientation as a quaternion, [x, y, z, w], in
+// base coordinates: x to the right, y up, z towards the player. A wand at rest
+// points away from the player, down -z.
+//
+// Quaternion components aren't angles, and reading them as if they were couples
+// the axes together and bends the response. These turn orientations into proper
+// angles, in radians:
+//
+//   roll  - about the wand's own long axis (z), what we call twist
+//   pitch - tipping the tip up or down (about x)
+//   yaw   - swinging the tip left or right (about y)
+//
+// Euler angles depend on the order the rotations are applied in, so that's left
+// to the caller. Yaw, then pitch, then roll suits a pointing device best: roll
+// stays the twist of the wand whichever way it's pointing.
+//
+
+pub type Quat = [f32; 4];
+pub type Mat3 = [[f32; 3]; 3];
+
+pub const IDENTITY: Quat = [0.0, 0.0, 0.0, 1.0];
+
+// Intrinsic rotation orders, named by the axes in the order they're applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub enum Order {
+    Xyz,
+    Xzy,
+    #[default]
+    Yxz,
+    Yzx,
+    Zxy,
+    Zyx,
+}
+
+impl Order {
+    fn axes (self) -> [usize; 3] {
+        match self {
+            Order::Xyz => [0, 1, 2],
+            Order::Xzy => [0, 2, 1],
+            Order::Yxz => [1, 0, 2],
+            Order::Yzx => [1, 2, 0],
+            Order::Zxy => [2, 0, 1],
+            Order::Zyx => [2, 1, 0],
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str (s: &str) -> Result<Order, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "xyz" => Ok(Order::Xyz),
+            "xzy" => Ok(Order::Xzy),
+            "yxz" => Ok(Order::Yxz),
+            "yzx" => Ok(Order::Yzx),
+            "zxy" => Ok(Order::Zxy),
+            "zyx" => Ok(Order::Zyx),
+            _ => Err(format!("unknown rotation order '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Angles {
+    pub roll:  f32,
+    pub pitch: f32,
+    pub yaw:   f32,
+}
+
+
+//
+// Module Functions
+//
+
+// Same hemisphere as `reference`. q and -q are the same orientation, but they
+// mustn't be mixed when averaging or comparing components.
+pub fn align (q: Quat, reference: Quat) -> Quat {
+    let dot = q[0]*reference[0] + q[1]*reference[1] + q[2]*reference[2] + q[3]*reference[3];
+    if dot < 0.0 { q.map(|c| -c) } else { q }
+}
+
+pub fn angles (q: Quat, order: Order) -> Angles {
+    angles_from_matrix(&to_matrix(q), order)
+}
+
+// Tait-Bryan angles for R = R_i(a) R_j(b) R_k(c). Near b = ±90° the first and
+// last axes line up and a and c can't be told apart; atan2 still gives a
+// consistent, if arbitrary, split.
+pub fn angles_from_matrix (m: &Mat3, order: Order) -> Angles {
+    let [i, j, k] = order.axes();
+    let sign = if j == (i + 1) % 3 { 1.0 } else { -1.0 };
+
+    let mut out = [0.0; 3];
+    out[j] = (sign * m[i][k]).clamp(-1.0, 1.0).asin();
+    out[i] = (-sign * m[j][k]).atan2(m[k][k]);
+    out[k] = (-sign * m[i][j]).atan2(m[i][i]);
+
+    Angles { pitch: out[0], yaw: out[1], roll: out[2] }
+}
+
+pub fn to_matrix (q: Quat) -> Mat3 {
+    let [x, y, z, w] = normalize(q);
+
+    [
+        [ 1.0 - 2.0 * (y*y + z*z),  2.0 * (x*y - z*w),        2.0 * (x*z + y*w)       ],
+        [ 2.0 * (x*y + z*w),        1.0 - 2.0 * (x*x + z*z),  2.0 * (y*z - x*w)       ],
+        [ 2.0 * (x*z - y*w),        2.0 * (y*z + x*w),        1.0 - 2.0 * (x*x + y*y) ],
+    ]
+}
+
+// Which way the wand points, as a unit vector in base coordinates: its rest
+// direction, -z, carried round by the rotation
+pub fn pointing (q: Quat) -> [f32; 3] {
+    let m = to_matrix(q);
+    [ -m[0][2], -m[1][2], -m[2][2] ]
+}
+
+// `b` as seen from `a`: the rotation that takes `a` to `b`
+pub fn relative (a: Quat, b: Quat) -> Quat {
+    multiply(conjugate(normalize(a)), normalize(b))
+}
+
+// Smallest angle between two orientations, 0 to π
+pub fn angle_between (a: Quat, b: Quat) -> f32 {
+    2.0 * relative(a, b)[3].abs().clamp(0.0, 1.0).acos()
+}
+
+// Angular velocity in base coordinates, radians per unit of `dt`, taking the
+// short way round
+pub fn angular_velocity (curr: Quat, prev: Quat, dt: f32) -> [f32; 3] {
+    let mut delta = multiply(normalize(curr), conjugate(normalize(prev)));
+
+    if delta[3] < 0.0 {
+        delta = delta.map(|c| -c);
+    }
+
+    let sin_half = (delta[0]*delta[0] + delta[1]*delta[1] + delta[2]*delta[2]).sqrt();
+
+    if sin_half < f32::EPSILON || dt <= 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+
+    let angle = 2.0 * sin_half.atan2(delta[3]);
+    let scale = angle / sin_half / dt;
+
+    [ delta[0] * scale, delta[1] * scale, delta[2] * scale ]
+}
+
+pub fn multiply (a: Quat, b: Quat) -> Quat {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+
+    [
+        aw*bx + ax*bw + ay*bz - az*by,
+        aw*by - ax*bz + ay*bw + az*bx,
+        aw*bz + ax*by - ay*bx + az*bw,
+        aw*bw - ax*bx - ay*by - az*bz,
+    ]
+}
+
+pub fn conjugate (q: Quat) -> Quat {
+    [ -q[0], -q[1], -q[2], q[3] ]
+}
+
+// Zero quaternions (eg. from a wand that isn't there) come back as the identity
+pub fn normalize (q: Quat) -> Quat {
+    let length = (q[0]*q[0] + q[1]*q[1] + q[2]*q[2] + q[3]*q[3]).sqrt();
+
+    if length < f32::EPSILON || !length.is_finite() {
+        return IDENTITY;
+    }
+
+    q.map(|c| c / length)
+}
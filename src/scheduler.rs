@@ -0,0 +1,151 @@
+
+use std::fmt;
+use std::thread::{sleep, yield_now};
+use std::time::{Duration, Instant};
+
+
+//
+// Scheduler
+//
+// Keeps the control loop on an exact period. Deadlines are laid out on a fixed
+// grid from the first tick, so a slow tick is paid back by a shorter wait on the
+// next one instead of pushing every later tick back. Any tick that misses its
+// deadline is an overrun and gets counted. If we fall more than a whole period
+// behind, the grid restarts from now rather than firing a burst of catch-up ticks.
+//
+// The OS sleep is only trusted to get us close, and the last stretch is spun.
+//
+
+const SPIN_MARGIN: Duration = Duration::from_micros(500);
+
+pub struct Scheduler {
+    pub period:   Duration,
+    pub overruns: u64,
+    pub interval: Duration,
+    deadline:     Instant,
+    last_tick:    Instant,
+}
+
+impl Scheduler {
+    pub fn new (period: Duration) -> Scheduler {
+        let now = Instant::now();
+        Scheduler {
+            period,
+            overruns: 0,
+            interval: period,
+            deadline: now + period,
+            last_tick: now,
+        }
+    }
+}
+
+pub fn wait (scheduler: &mut Scheduler) {
+    let now = Instant::now();
+
+    if now > scheduler.deadline {
+        scheduler.overruns += 1;
+    }
+
+    if now > scheduler.deadline + scheduler.period {
+        scheduler.deadline = now;
+    } else {
+        if let Some(remaining) = scheduler.deadline.checked_duration_since(now) {
+            sleep(remaining.saturating_sub(SPIN_MARGIN));
+        }
+        while Instant::now() < scheduler.deadline {
+            yield_now();
+        }
+    }
+
+    let now = Instant::now();
+    scheduler.interval  = now - scheduler.last_tick;
+    scheduler.last_tick = now;
+    scheduler.deadline += scheduler.period;
+}
+
+
+//
+// Stats
+//
+// Running min/avg/max plus a histogram for percentiles, so the summary covers the
+// whole session without keeping every sample around.
+//
+
+const BUCKET_WIDTH: Duration = Duration::from_micros(10);
+const BUCKETS:      usize    = 5000;   // 50ms; anything slower lands in the last bucket
+
+#[derive(Clone)]
+pub struct Stats {
+    count:     u64,
+    total:     Duration,
+    min:       Duration,
+    max:       Duration,
+    histogram: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub count: u64,
+    pub min:   Duration,
+    pub avg:   Duration,
+    pub max:   Duration,
+    pub p99:   Duration,
+}
+
+impl Stats {
+    pub fn new () -> Stats {
+        Stats {
+            count:     0,
+            total:     Duration::ZERO,
+            min:       Duration::MAX,
+            max:       Duration::ZERO,
+            histogram: vec![0; BUCKETS],
+        }
+    }
+}
+
+pub fn record (stats: &mut Stats, sample: Duration) {
+    let bucket = (sample.as_nanos() / BUCKET_WIDTH.as_nanos()) as usize;
+
+    stats.count += 1;
+    stats.total += sample;
+    stats.min    = stats.min.min(sample);
+    stats.max    = stats.max.max(sample);
+    stats.histogram[bucket.min(BUCKETS - 1)] += 1;
+}
+
+pub fn summary (stats: &Stats) -> Summary {
+    if stats.count == 0 {
+        return Summary::default();
+    }
+
+    Summary {
+        count: stats.count,
+        min:   stats.min,
+        avg:   Duration::from_secs_f64(stats.total.as_secs_f64() / stats.count as f64),
+        max:   stats.max,
+        p99:   percentile(stats, 0.99),
+    }
+}
+
+fn percentile (stats: &Stats, p: f64) -> Duration {
+    let target = (stats.count as f64 * p).ceil() as u64;
+    let mut seen = 0;
+
+    for (ix, n) in stats.histogram.iter().enumerate() {
+        seen += *n as u64;
+        if seen >= target {
+            return BUCKET_WIDTH * (ix as u32 + 1);
+        }
+    }
+
+    stats.max
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn ms (d: Duration) -> f32 { d.as_secs_f32() * 1000.0 }
+        write!(f, "min {:5.2}  avg {:5.2}  max {:5.2}  p99 {:5.2} ms",
+            ms(self.min), ms(self.avg), ms(self.max), ms(self.p99))
+    }
+}
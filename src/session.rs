@@ -0,0 +1,260 @@
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{filter, hydra, midi, zgicabra};
+use crate::hydra::{HydraState, ControllerFrame};
+use crate::midi_event::MidiEvent;
+use crate::modulation::{Mapping, Modulation};
+use crate::zgicabra::{Zgicabra, DeltaEvent, Filtering};
+
+
+//
+// Session
+//
+// A recorded controller session is a JSON-lines file. The first line is a header
+// carrying the format version, and every line after that is one tick of raw hydra
+// data along with how long that tick took:
+//
+//   { "version": 1 }
+//   { "dt_us": 10214, "controllers": [ { "pos": [...], ... }, { ... } ] }
+//
+// Controllers are in HydraState order, left then right for each player. Older
+// recordings only have player one's pair, and any missing wands play back as
+// disabled.
+//
+// Replaying the frames through zgicabra::update with the recorded dt, and the same
+// calibration, gives exactly the same gestures and MIDI as the original performance.
+//
+
+pub const SESSION_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionFrame {
+    pub dt_us:       u64,
+    pub controllers: Vec<ControllerFrame>,
+}
+
+impl SessionFrame {
+    pub fn timedelta (&self) -> Duration {
+        Duration::from_micros(self.dt_us)
+    }
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+
+//
+// Recording
+//
+
+pub fn start_recording (path: &str) -> io::Result<Recorder> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", serde_json::to_string(&Header { version: SESSION_VERSION })?)?;
+    Ok(Recorder { writer })
+}
+
+pub fn record (recorder: &mut Recorder, hydra_state: &HydraState) -> io::Result<()> {
+    let frame = SessionFrame {
+        dt_us:       hydra_state.timedelta.as_micros() as u64,
+        controllers: hydra_state.controllers.to_vec(),
+    };
+    writeln!(recorder.writer, "{}", serde_json::to_string(&frame)?)
+}
+
+pub fn stop_recording (mut recorder: Recorder) -> io::Result<()> {
+    recorder.writer.flush()
+}
+
+
+//
+// Playback
+//
+
+pub fn load (path: &str) -> io::Result<Vec<SessionFrame>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "empty session file")),
+    };
+
+    if header.version != SESSION_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("session version {} is not supported (expected {})", header.version, SESSION_VERSION)));
+    }
+
+    let mut frames = Vec::new();
+    for line in lines {
+        frames.push(serde_json::from_str(&line?)?);
+    }
+    Ok(frames)
+}
+
+pub fn apply_frame (frame: &SessionFrame, hydra_state: &mut HydraState) {
+    for (slot, controller) in hydra_state.controllers.iter_mut().enumerate() {
+        *controller = frame.controllers.get(slot).copied().unwrap_or_default();
+    }
+    hydra_state.timedelta = frame.timedelta();
+}
+
+
+//
+// Replay
+//
+// Runs recorded frames through gestures and MIDI for player one, starting from
+// the given instrument (with its calibration and settings) and fresh filters,
+// and hands each tick's MIDI to `each` along with how long the tick was. Nothing
+// in here is random, so the same frames always give the same MIDI.
+//
+
+pub fn replay (frames: &[SessionFrame], mut zgicabra: Zgicabra, filter: filter::Settings, mappings: &[Mapping], mut each: impl FnMut(&[MidiEvent], Duration)) {
+    let mut hydra_state  = HydraState::new();
+    let mut filtering    = Filtering::new(filter);
+    let mut modulation   = Modulation::new(mappings);
+    let mut previous     = zgicabra.clone();
+    let mut midi_events:  Vec<MidiEvent>  = Vec::new();
+    let mut delta_events: Vec<DeltaEvent> = Vec::new();
+
+    for frame in frames.iter() {
+        apply_frame(frame, &mut hydra_state);
+
+        let (left, right) = hydra::wands(&hydra_state, 0);
+        zgicabra::update(&mut zgicabra, &previous, &mut filtering, left, right, hydra_state.timedelta, &mut delta_events);
+        midi::update(&zgicabra, &mut modulation, &delta_events, &mut midi_events);
+        each(&midi_events, hydra_state.timedelta);
+
+        midi_events.clear();
+        delta_events.clear();
+        previous = zgicabra.clone();
+    }
+}
+
+
+//
+// Offline Render
+//
+// Replays a recorded session through the built-in synth and writes the result to
+// a WAV file. Neither the replay nor the synth draws on the RNG, so two renders
+// of the same session with the same mappings are bit-identical. The RNG is seeded
+// all the same, so anything random added to the synth later can't quietly break
+// that. Only player one is rendered, since the built-in synth is a single voice.
+//
+
+#[cfg(feature = "synth")]
+pub fn render (session_path: &str, wav_path: &str, seed: u64, zgicabra: Zgicabra, filter: filter::Settings, mappings: &[Mapping]) -> Result<usize, Box<dyn std::error::Error>> {
+    use crate::{synth, tools};
+
+    let frames = load(session_path)?;
+
+    tools::seed_rng(seed);
+
+    let mut output = synth::start(Some(wav_path.to_string()), false)?;
+
+    replay(&frames, zgicabra, filter, mappings, |midi_events, dt| synth::update(&mut output, midi_events, dt));
+
+    synth::stop(output)?;
+
+    Ok(frames.len())
+}
+
+
+//
+// Test Fixtures
+//
+// Made-up sessions, for tests that need frames to play through
+//
+
+#[cfg(test)]
+pub mod fixtures {
+    use super::*;
+
+    pub const DT_US: u64 = 10_000;
+
+    pub fn wand (which_hand: u8, pos: [f32; 3], trigger: f32, sequence: u8) -> ControllerFrame {
+        ControllerFrame {
+            pos,
+            rot_quat:        crate::orientation::IDENTITY,
+            trigger,
+            sequence_number: sequence,
+            enabled:         1,
+            which_hand,
+            ..ControllerFrame::new()
+        }
+    }
+
+    // Both wands swaying in front of the base, triggers squeezed now and then,
+    // the left stick going round and the right wand twisting
+    pub fn performance (ticks: usize) -> Vec<SessionFrame> {
+        (0..ticks).map(|tick| {
+            let t = tick as f32 * DT_US as f32 / 1.0e6;
+            let squeezed = (tick / 50) % 2 == 1;
+            let trigger  = if squeezed { 0.8 } else { 0.0 };
+
+            let mut left  = wand(hydra::LEFT_HAND,  [-200.0 + 50.0 * t.sin(), 100.0, -300.0], trigger, tick as u8);
+            let mut right = wand(hydra::RIGHT_HAND, [ 200.0, 100.0 + 40.0 * t.cos(), -300.0], trigger, tick as u8);
+
+            left.joystick_x  = (t * 3.0).cos();
+            left.joystick_y  = (t * 3.0).sin();
+            right.rot_quat   = [0.0, 0.0, (0.3 * t.sin()).sin(), (0.3 * t.sin()).cos()];
+
+            SessionFrame { dt_us: DT_US, controllers: vec![left, right] }
+        }).collect()
+    }
+}
+
+
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi_from (frames: &[SessionFrame]) -> Vec<MidiEvent> {
+        let mut all = Vec::new();
+        replay(frames, Zgicabra::new(), filter::Settings::default(), &[], |midi_events, _| all.extend_from_slice(midi_events));
+        all
+    }
+
+    #[test]
+    fn replay_is_deterministic () {
+        let frames = fixtures::performance(400);
+        let first  = midi_from(&frames);
+        let second = midi_from(&frames);
+
+        assert!(first.iter().any(|event| event.msg & 0xF0 == crate::midi_event::MSG_NOTE_ON));
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn recording_round_trips () {
+        let path   = std::env::temp_dir().join(format!("zgicabra-session-{}.jsonl", std::process::id()));
+        let path   = path.to_str().unwrap();
+        let frames = fixtures::performance(20);
+
+        let mut recorder    = start_recording(path).unwrap();
+        let mut hydra_state = HydraState::new();
+        for frame in frames.iter() {
+            apply_frame(frame, &mut hydra_state);
+            record(&mut recorder, &hydra_state).unwrap();
+        }
+        stop_recording(recorder).unwrap();
+
+        let loaded = load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(midi_from(&loaded), midi_from(&frames));
+    }
+}
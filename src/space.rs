@@ -0,0 +1,54 @@
+use serde::Serialize;
+
+use crate::orientation;
+use crate::hydra::ControllerFrame;
+
+
+//
+// Playing Space
+//
+// The Hydra reports everything relative to its base, so walking round the stage,
+// or knocking the base, moves every position and turns every orientation. A
+// player's space is where they're standing and which way they're facing: an
+// origin, and a turn about the vertical. Readings are moved into it before
+// anything else (calibration included) sees them.
+//
+// The default space is the base's own, which leaves readings as they are.
+//
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Space {
+    pub origin: [f32; 3],   // Base coordinates, mm
+    pub facing: f32,        // Radians about the vertical, 0 is facing down -z
+}
+
+
+//
+// Module Functions
+//
+
+// The space a player is in right now, from both wands: centred between them,
+// facing where they point between them
+pub fn capture (left: &ControllerFrame, right: &ControllerFrame) -> Space {
+    let a = orientation::pointing(left.rot_quat);
+    let b = orientation::pointing(right.rot_quat);
+
+    Space {
+        origin: [0, 1, 2].map(|i| (left.pos[i] + right.pos[i]) / 2.0),
+        facing: (-(a[0] + b[0])).atan2(-(a[2] + b[2])),
+    }
+}
+
+// A frame as seen from inside the space. Only the position and quaternion are
+// moved, since nothing reads rot_mat.
+pub fn apply (space: &Space, frame: &ControllerFrame) -> ControllerFrame {
+    let offset = [0, 1, 2].map(|i| frame.pos[i] - space.origin[i]);
+    let (sin, cos) = (-space.facing).sin_cos();
+    let turn = [0.0, (-space.facing / 2.0).sin(), 0.0, (-space.facing / 2.0).cos()];
+
+    ControllerFrame {
+        pos:      [ offset[0] * cos + offset[2] * sin, offset[1], offset[2] * cos - offset[0] * sin ],
+        rot_quat: orientation::multiply(turn, frame.rot_quat),
+        ..*frame
+    }
+}
@@ -0,0 +1,399 @@
+
+use std::error::Error;
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::midi;
+use crate::midi_event::*;
+use crate::zgicabra::Voice;
+
+
+//
+// Synth
+//
+// A small monophonic engine so the instrument makes noise without a DAW. It
+// listens to exactly the same MidiEvent stream that goes out over the wire, so
+// what you hear here is what a hardware synth would be told to play.
+//
+// Each Voice selects a Patch via program change:
+//
+//   Classic    - polyblep saw through a resonant lowpass
+//   Eternal    - detuned saw pair, slow swell and long tail
+//   Pennysack  - square wave into a hard fuzz stage
+//   Submission - sine with a pitch-dropping sub thump on every note
+//
+// The filter cutoff follows CC_CUTOFF (signal.filter), and CC_FUZZ, CC_THUMP and
+// CC_WIDTH push the drive, sub level and detune of whichever patch is active.
+//
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+const BEND_RANGE:  f32 = 2.0;   // Semitones either way at full bend
+const MASTER_GAIN: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy)]
+enum Osc {
+    Saw,
+    Square,
+    Sine,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Patch {
+    osc:          Osc,
+    detune:       f32,   // Cents between the two oscillators
+    cutoff_min:   f32,   // Hz at filter = 0
+    cutoff_max:   f32,   // Hz at filter = 1
+    resonance:    f32,
+    drive:        f32,
+    sub:          f32,
+    thump_drop:   f32,   // Octaves the sub falls through on note start
+    attack:       f32,   // Seconds
+    release:      f32,   // Seconds
+}
+
+impl Patch {
+    pub fn for_voice (voice: Voice) -> Patch {
+        match voice {
+            Voice::Classic => Patch {
+                osc: Osc::Saw,    detune:  0.0, cutoff_min: 120.0, cutoff_max: 9000.0, resonance: 0.35,
+                drive: 0.1, sub: 0.15, thump_drop: 0.0, attack: 0.005, release: 0.12,
+            },
+            Voice::Eternal => Patch {
+                osc: Osc::Saw,    detune: 14.0, cutoff_min: 200.0, cutoff_max: 6000.0, resonance: 0.15,
+                drive: 0.0, sub: 0.0,  thump_drop: 0.0, attack: 0.25,  release: 1.5,
+            },
+            Voice::Pennysack => Patch {
+                osc: Osc::Square, detune:  4.0, cutoff_min: 300.0, cutoff_max: 7000.0, resonance: 0.2,
+                drive: 0.8, sub: 0.1,  thump_drop: 0.0, attack: 0.002, release: 0.08,
+            },
+            Voice::Submission => Patch {
+                osc: Osc::Sine,   detune:  0.0, cutoff_min: 80.0,  cutoff_max: 3000.0, resonance: 0.1,
+                drive: 0.3, sub: 0.8,  thump_drop: 2.0, attack: 0.001, release: 0.3,
+            },
+        }
+    }
+}
+
+pub struct Synth {
+    pub sample_rate: u32,
+    pub voice:       Voice,
+    pub recording:   Option<Vec<f32>>,
+    patch:     Patch,
+    note:      Option<u8>,
+    gate:      bool,
+    velocity:  f32,
+    bend:      f32,
+    cutoff:    f32,
+    fuzz:      f32,
+    thump:     f32,
+    width:     f32,
+    env:       f32,
+    thump_env: f32,
+    phase:     [f32; 3],
+    low:       f32,
+    band:      f32,
+}
+
+impl Synth {
+    pub fn new (sample_rate: u32) -> Synth {
+        Synth {
+            sample_rate,
+            voice:     Voice::Classic,
+            recording: None,
+            patch:     Patch::for_voice(Voice::Classic),
+            note:      None,
+            gate:      false,
+            velocity:  0.0,
+            bend:      0.0,
+            cutoff:    0.5,
+            fuzz:      0.0,
+            thump:     0.0,
+            width:     0.0,
+            env:       0.0,
+            thump_env: 0.0,
+            phase:     [0.0, 0.0, 0.0],
+            low:       0.0,
+            band:      0.0,
+        }
+    }
+}
+
+
+//
+// Module Functions
+//
+
+// Only listens on channel 1, which is player one
+pub fn handle (synth: &mut Synth, midi_events: &[MidiEvent]) {
+    let mut events = midi_events.iter().filter(|event| event.channel() == 0).peekable();
+
+    while let Some(event) = events.next() {
+        match event.msg & 0xF0 {
+            MSG_NOTE_ON if event.lsb > 0 => {
+                if !synth.gate { synth.thump_env = 1.0; }
+                synth.note     = Some(event.msb);
+                synth.gate     = true;
+                synth.velocity = event.lsb as f32 / 127.0;
+            },
+
+            // A note change goes out as note-off then note-on (see midi::update).
+            // Played legato like that, the gate stays open and the thump doesn't
+            // come back on every step.
+            MSG_NOTE_ON | MSG_NOTE_OFF if synth.note == Some(event.msb) => {
+                let legato = events.peek().is_some_and(|next| next.msg & 0xF0 == MSG_NOTE_ON && next.lsb > 0);
+                if !legato {
+                    synth.gate = false;
+                }
+            },
+
+            MSG_PITCH_BEND => {
+                let value = (event.lsb as i32) << 7 | event.msb as i32;
+                synth.bend = ((value - 8192) as f32 / 8192.0).clamp(-1.0, 1.0);
+            },
+
+            MSG_PROGRAM_CHANGE => {
+                synth.voice = voice_from_program(event.msb);
+                synth.patch = Patch::for_voice(synth.voice);
+            },
+
+            MSG_CONTROL_CHANGE => {
+                let value = event.lsb as f32 / 127.0;
+                match event.msb {
+                    midi::CC_CUTOFF => synth.cutoff = value,
+                    midi::CC_FUZZ   => synth.fuzz   = value,
+                    midi::CC_THUMP  => synth.thump  = value,
+                    midi::CC_WIDTH  => synth.width  = value,
+                    CC_MIDI_PANIC | CC_SILENCE => {
+                        synth.gate = false;
+                        synth.env  = 0.0;
+                    },
+                    _ => {},
+                }
+            },
+
+            _ => {},
+        }
+    }
+}
+
+pub fn render (synth: &mut Synth, out: &mut [f32]) {
+    let sr = synth.sample_rate as f32;
+    let p  = synth.patch;
+
+    let attack_step  = 1.0 / (p.attack  * sr).max(1.0);
+    let release_step = 1.0 / (p.release * sr).max(1.0);
+    let thump_decay  = (-1.0 / (0.08 * sr)).exp();
+
+    let cutoff = p.cutoff_min * (p.cutoff_max / p.cutoff_min).powf(synth.cutoff);
+    let f      = 2.0 * (PI * cutoff.min(sr / 6.0) / sr).sin();
+    let q      = 1.0 - p.resonance.clamp(0.0, 0.95);
+    let drive  = 1.0 + 12.0 * (p.drive + synth.fuzz).min(1.5);
+    let sub    = (p.sub + synth.thump).min(1.0);
+    let detune = p.detune + synth.width * 25.0;
+
+    let note = synth.note.unwrap_or(0) as f32 + synth.bend * BEND_RANGE;
+    let freq = 440.0 * 2.0_f32.powf((note - 69.0) / 12.0);
+
+    for sample in out.iter_mut() {
+        synth.env = if synth.gate {
+            (synth.env + attack_step).min(1.0)
+        } else {
+            (synth.env - release_step).max(0.0)
+        };
+
+        let f1 = freq * cents(-detune / 2.0);
+        let f2 = freq * cents( detune / 2.0);
+        let fs = freq / 2.0 * 2.0_f32.powf(p.thump_drop * synth.thump_env);
+
+        let mut x = oscillator(p.osc, synth.phase[0], f1 / sr);
+        if detune != 0.0 {
+            x = 0.5 * (x + oscillator(p.osc, synth.phase[1], f2 / sr));
+        }
+
+        // Chamberlin state-variable lowpass
+        let high   = x - synth.low - q * synth.band;
+        synth.band += f * high;
+        synth.low  += f * synth.band;
+
+        let body  = (synth.low * drive).tanh() / drive.tanh();
+        let thump = sub * (2.0 * PI * synth.phase[2]).sin() * (0.5 + 0.5 * synth.thump_env);
+
+        *sample = MASTER_GAIN * synth.env * synth.velocity * (body + thump);
+
+        synth.phase[0] = (synth.phase[0] + f1 / sr) % 1.0;
+        synth.phase[1] = (synth.phase[1] + f2 / sr) % 1.0;
+        synth.phase[2] = (synth.phase[2] + fs / sr) % 1.0;
+        synth.thump_env *= thump_decay;
+    }
+
+    if let Some(tape) = synth.recording.as_mut() {
+        tape.extend_from_slice(out);
+    }
+}
+
+pub fn write_wav (path: &str, sample_rate: u32, samples: &[f32]) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels:        1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format:   hound::SampleFormat::Int,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()
+}
+
+
+//
+// Output
+//
+// Owns a Synth for the main loop. Without a live device it renders `dt` worth of
+// samples every tick, which is plenty for capturing a WAV of the session. With the
+// `synth-live` feature the sound card pulls samples instead and we just forward
+// events to it.
+//
+
+pub struct Output {
+    pub synth: Arc<Mutex<Synth>>,
+    wav_path:  Option<String>,
+    leftover:  f32,
+    #[cfg(feature = "synth-live")]
+    stream:    Option<cpal::Stream>,
+}
+
+pub fn start (wav_path: Option<String>, live: bool) -> Result<Output, Box<dyn Error>> {
+    let synth = Arc::new(Mutex::new(Synth::new(DEFAULT_SAMPLE_RATE)));
+
+    if wav_path.is_some() {
+        synth.lock().unwrap().recording = Some(Vec::new());
+    }
+
+    #[cfg(feature = "synth-live")]
+    let stream = if live { Some(start_live(&synth)?) } else { None };
+
+    #[cfg(not(feature = "synth-live"))]
+    if live {
+        return Err("live synth output needs the `synth-live` feature".into());
+    }
+
+    Ok(Output {
+        synth,
+        wav_path,
+        leftover: 0.0,
+        #[cfg(feature = "synth-live")]
+        stream,
+    })
+}
+
+pub fn update (output: &mut Output, midi_events: &[MidiEvent], dt: Duration) {
+    let mut synth = output.synth.lock().unwrap();
+
+    handle(&mut synth, midi_events);
+
+    if !is_live(output) {
+        let samples    = dt.as_secs_f32() * synth.sample_rate as f32 + output.leftover;
+        output.leftover = samples.fract();
+
+        let mut buffer = vec![0.0; samples as usize];
+        render(&mut synth, &mut buffer);
+    }
+}
+
+pub fn stop (output: Output) -> Result<(), Box<dyn Error>> {
+    let mut synth = output.synth.lock().unwrap();
+
+    if let (Some(path), Some(tape)) = (output.wav_path.as_ref(), synth.recording.take()) {
+        write_wav(path, synth.sample_rate, &tape)?;
+    }
+
+    Ok(())
+}
+
+
+//
+// Live Output
+//
+
+#[cfg(feature = "synth-live")]
+fn start_live (synth: &Arc<Mutex<Synth>>) -> Result<cpal::Stream, Box<dyn Error>> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    let host   = cpal::default_host();
+    let device = host.default_output_device().ok_or("no audio output device")?;
+    let config: cpal::StreamConfig = device.default_output_config()?.into();
+    let channels = config.channels as usize;
+
+    synth.lock().unwrap().sample_rate = config.sample_rate.0;
+
+    let synth  = synth.clone();
+    let mut mono = Vec::new();
+
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _| {
+            mono.resize(data.len() / channels, 0.0);
+            render(&mut synth.lock().unwrap(), &mut mono);
+            for (frame, sample) in data.chunks_mut(channels).zip(mono.iter()) {
+                frame.fill(*sample);
+            }
+        },
+        |err| eprintln!("Synth - output stream error: {}", err),
+        None)?;
+
+    stream.play()?;
+    Ok(stream)
+}
+
+fn is_live (output: &Output) -> bool {
+    #[cfg(feature = "synth-live")]
+    { output.stream.is_some() }
+
+    #[cfg(not(feature = "synth-live"))]
+    { false }
+}
+
+
+//
+// Helpers
+//
+
+fn voice_from_program (program: u8) -> Voice {
+    match program % 4 {
+        0 => Voice::Classic,
+        1 => Voice::Eternal,
+        2 => Voice::Pennysack,
+        _ => Voice::Submission,
+    }
+}
+
+fn cents (c: f32) -> f32 {
+    2.0_f32.powf(c / 1200.0)
+}
+
+fn oscillator (osc: Osc, phase: f32, step: f32) -> f32 {
+    match osc {
+        Osc::Sine   => (2.0 * PI * phase).sin(),
+        Osc::Saw    => 2.0 * phase - 1.0 - poly_blep(phase, step),
+        Osc::Square => {
+            let square = if phase < 0.5 { 1.0 } else { -1.0 };
+            square + poly_blep(phase, step) - poly_blep((phase + 0.5) % 1.0, step)
+        },
+    }
+}
+
+fn poly_blep (t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
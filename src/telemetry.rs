@@ -0,0 +1,220 @@
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tungstenite::{WebSocket, Message};
+
+use crate::zgicabra::{Zgicabra, DeltaEvent};
+use crate::midi_event::MidiEvent;
+use crate::control::Player;
+use crate::tools::time_now;
+
+
+//
+// Telemetry
+//
+// Streams the instrument state to any number of WebSocket clients as JSON text
+// frames. Each frame is one Snapshot:
+//
+//   {
+//     "version": 4,             // TELEMETRY_VERSION, bumped on breaking changes
+//     "tick":    1234,          // control loop tick this snapshot was taken on
+//     "time":    12.34,         // seconds since startup
+//     "players": [              // one entry per player, in order
+//       {
+//         "channel": 0,         // MIDI channel, 0-based
+//         "state":   { ... },   // the full Zgicabra struct, field names as in zgicabra.rs
+//         "deltas":  [ ... ]    // every DeltaEvent since the previous snapshot
+//       }
+//     ],
+//     "midi":    [ ... ]        // every MidiEvent since the previous snapshot, as { msg, msb, lsb }
+//   }
+//
+// Version 1 had a single player's "state" and "deltas" at the top level. Up to
+// version 2 each wand's vel, acc and jerk were per millisecond; from version 3
+// they're m/s, m/s² and m/s³, so vel reads the same, acc ×1000 and jerk ×10⁶.
+// Version 4 replaced "wand_angle" with "geometry", which has it as "angle" along
+// with the rest of how the hands sit relative to each other.
+//
+// DeltaEvents use serde's default enum encoding, eg. { "NoteChange": [42, 44] } or
+// { "Panic": [] }. Snapshots are sent at most `rate` times per second; events from
+// skipped ticks are accumulated so nothing is lost. If the broadcaster is backed
+// up, the snapshot is tried again next tick with the events still accumulating.
+//
+// Clients get HANDSHAKE_TIMEOUT to finish the WebSocket handshake, each on its
+// own thread, so one that stalls can't hold up anyone else connecting.
+//
+// Try it with any local client, eg. `websocat ws://127.0.0.1:9001`
+//
+
+pub const TELEMETRY_VERSION: u32 = 4;
+
+const SEND_QUEUE_LENGTH: usize = 16;
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+const HANDSHAKE_TIMEOUT:    Duration = Duration::from_secs(2);
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+#[derive(Serialize)]
+struct PlayerState<'a> {
+    channel: u8,
+    state:   &'a Zgicabra,
+    deltas:  &'a [DeltaEvent],
+}
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    version: u32,
+    tick:    u64,
+    time:    f32,
+    players: Vec<PlayerState<'a>>,
+    midi:    &'a [MidiEvent],
+}
+
+pub struct Telemetry {
+    pub tick:     u64,
+    pub interval: Duration,
+    pub addr:     SocketAddr,   // Where we're listening, with the port filled in
+    last_sent:    Instant,
+    deltas:       Vec<Vec<DeltaEvent>>,   // Per player
+    midi:         Vec<MidiEvent>,
+    sender:       SyncSender<String>,
+}
+
+
+//
+// Module Functions
+//
+
+pub fn start (addr: &str, rate: f32) -> io::Result<Telemetry> {
+    let listener = TcpListener::bind(addr)?;
+    let addr     = listener.local_addr()?;
+    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+    let (sender, receiver) = sync_channel::<String>(SEND_QUEUE_LENGTH);
+
+    let accept_clients = clients.clone();
+    thread::spawn(move || accept_loop(listener, accept_clients));
+    thread::spawn(move || broadcast_loop(receiver, clients));
+
+    Ok(Telemetry {
+        tick:      0,
+        interval:  Duration::from_secs_f32(1.0 / rate.max(1.0)),
+        addr,
+        last_sent: Instant::now(),
+        deltas:    Vec::new(),
+        midi:      Vec::new(),
+        sender,
+    })
+}
+
+pub fn update (telemetry: &mut Telemetry, players: &[Player], midi_events: &[MidiEvent]) {
+    telemetry.tick += 1;
+    telemetry.deltas.resize_with(players.len(), Vec::new);
+    telemetry.midi.extend_from_slice(midi_events);
+
+    for (deltas, player) in telemetry.deltas.iter_mut().zip(players) {
+        deltas.extend_from_slice(&player.delta_events);
+    }
+
+    if telemetry.last_sent.elapsed() < telemetry.interval {
+        return;
+    }
+
+    let snapshot = Snapshot {
+        version: TELEMETRY_VERSION,
+        tick:    telemetry.tick,
+        time:    time_now(),
+        players: players.iter().zip(&telemetry.deltas).map(|(player, deltas)| PlayerState {
+            channel: player.channel,
+            state:   &player.zgicabra,
+            deltas,
+        }).collect(),
+        midi:    &telemetry.midi,
+    };
+
+    // If the broadcaster is backed up we keep the events for next tick rather than
+    // stall the control loop
+    if let Ok(json) = serde_json::to_string(&snapshot) {
+        if let Err(TrySendError::Full(_)) = telemetry.sender.try_send(json) {
+            return;
+        }
+    }
+
+    telemetry.deltas.iter_mut().for_each(Vec::clear);
+    telemetry.midi.clear();
+    telemetry.last_sent = Instant::now();
+}
+
+
+//
+// Worker Threads
+//
+
+fn accept_loop (listener: TcpListener, clients: Clients) {
+    for stream in listener.incoming().flatten() {
+        let clients = clients.clone();
+        thread::spawn(move || handshake(stream, clients));
+    }
+}
+
+fn handshake (stream: TcpStream, clients: Clients) {
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT));
+    let _ = stream.set_nodelay(true);
+
+    if let Ok(socket) = tungstenite::accept(stream) {
+        let _ = socket.get_ref().set_read_timeout(None);
+        clients.lock().unwrap().push(socket);
+    }
+}
+
+fn broadcast_loop (receiver: Receiver<String>, clients: Clients) {
+    for json in receiver.iter() {
+        let mut clients = clients.lock().unwrap();
+
+        // Clients that fail to take a frame are assumed gone
+        clients.retain_mut(|socket| socket.send(Message::text(json.clone())).is_ok());
+    }
+}
+
+
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn local_client_gets_versioned_snapshots () {
+        let mut telemetry = start("127.0.0.1:0", 1000.0).unwrap();
+        let players = vec![Player::new(0, false)];
+
+        let (mut client, _) = tungstenite::connect(format!("ws://{}", telemetry.addr)).unwrap();
+        if let tungstenite::stream::MaybeTlsStream::Plain(stream) = client.get_ref() {
+            stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        }
+
+        // The server only knows about the client once its side of the handshake is done
+        for _ in 0..100 {
+            sleep(Duration::from_millis(5));
+            update(&mut telemetry, &players, &[]);
+
+            if let Ok(Message::Text(text)) = client.read() {
+                let snapshot: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(snapshot["version"], TELEMETRY_VERSION);
+                assert_eq!(snapshot["players"].as_array().unwrap().len(), 1);
+                return;
+            }
+        }
+
+        panic!("no snapshot arrived");
+    }
+}
@@ -1,14 +1,17 @@
 
 use core::f32::consts::PI;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use rand::prelude::*;
+use rand::rngs::StdRng;
 use rand_distr::StandardNormal;
 
 use lazy_static::lazy_static;
 
 lazy_static! {
     static ref START_TIME: Instant = Instant::now();
+    static ref RNG: Mutex<StdRng> = Mutex::new(StdRng::from_entropy());
 }
 
 const NOTE_NAME:&str = "C C#D D#E F F#G G#A A#B ";
@@ -52,13 +55,18 @@ pub fn ncos (phase: f32) -> f32 {
 
 
 // Random Numbers
+// All randomness goes through one shared generator so offline renders can be seeded
+
+pub fn seed_rng (seed: u64) {
+    *RNG.lock().unwrap() = StdRng::seed_from_u64(seed);
+}
 
 pub fn rand_normal (n: f32) -> f32 {
-    n * rand::thread_rng().sample::<f32,_>(StandardNormal)
+    n * RNG.lock().unwrap().sample::<f32,_>(StandardNormal)
 }
 
 pub fn rand_uniform (n: f32) -> f32 {
-    n * rand::random::<f32>()
+    n * RNG.lock().unwrap().gen::<f32>()
 }
 
 
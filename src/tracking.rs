@@ -0,0 +1,214 @@
+use serde::Serialize;
+
+
+//
+// Tracking
+//
+// The Hydra finds its wands magnetically, and the field looks the same from
+// either side of the base: (x, y, z) and (-x, -y, -z) can't be told apart. The
+// SDK's hemisphere tracking follows which side each wand is on, but until it's
+// on (see hydra::update), or if it loses its place, a wand crossing behind the
+// base has its position mirrored through the base in one frame. Differentiated,
+// that's a speed no hand could manage.
+//
+// Every raw position is checked against the last good one. One that got there
+// impossibly fast is a glitch:
+//
+//   - if its mirror image is near where the wand was, it's a hemisphere flip, and
+//     every reading is mirrored back from then until it flips again
+//   - otherwise the last good position is held, until MAX_HOLD has gone by and
+//     the wand must really be somewhere else
+//
+// Confidence drops with each glitch and recovers over CONFIDENCE_RECOVERY seconds
+// of clean readings, so anything that cares can tell shaky data from good.
+//
+
+const MAX_SPEED:           f32 = 10_000.0;   // mm/s, faster than any gesture
+const MAX_HOLD:            f32 = 0.25;       // Seconds before a jump is believed
+const CONFIDENCE_RECOVERY: f32 = 1.0;        // Seconds from nothing back to full
+const FLIP_PENALTY:        f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Reading {
+    Good,
+    Flipped,    // Mirrored back through the base
+    Held,       // Too far, too fast, and not a flip either
+    Moved,      // Held too long, so taken as it is
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Tracker {
+    pub confidence: f32,        // 0 to 1
+    pub flipped:    bool,       // Currently mirroring readings back
+    pub reading:    Reading,    // What happened to the last one
+    last_good:      Option<[f32; 3]>,
+    since_good:     f32,        // Seconds since the last good reading
+}
+
+impl Default for Tracker {
+    fn default () -> Tracker {
+        Tracker {
+            confidence: 1.0,
+            flipped:    false,
+            reading:    Reading::Good,
+            last_good:  None,
+            since_good: 0.0,
+        }
+    }
+}
+
+
+//
+// Module Functions
+//
+
+// The position to believe, `dt` seconds after the last reading
+pub fn check (tracker: &mut Tracker, raw: [f32; 3], dt: f32) -> [f32; 3] {
+    if !raw.iter().all(|c| c.is_finite()) {
+        tracker.reading = Reading::Held;
+        return tracker.last_good.unwrap_or(raw);
+    }
+
+    let pos = if tracker.flipped { mirror(raw) } else { raw };
+
+    let Some(last) = tracker.last_good else {
+        tracker.last_good = Some(pos);
+        tracker.reading   = Reading::Good;
+        return pos;
+    };
+
+    let dt = dt.max(0.0);
+    let reach = MAX_SPEED * dt;
+    tracker.since_good += dt;
+
+    let (pos, reading) = if distance(pos, last) <= reach {
+        (pos, Reading::Good)
+    } else if distance(mirror(pos), last) <= reach {
+        tracker.flipped = !tracker.flipped;
+        (mirror(pos), Reading::Flipped)
+    } else if tracker.since_good < MAX_HOLD {
+        (last, Reading::Held)
+    } else {
+        (pos, Reading::Moved)
+    };
+
+    tracker.confidence = match reading {
+        Reading::Good    => (tracker.confidence + dt / CONFIDENCE_RECOVERY).min(1.0),
+        Reading::Flipped => tracker.confidence * FLIP_PENALTY,
+        Reading::Held    => 0.0,
+        Reading::Moved   => 0.0,
+    };
+
+    if reading != Reading::Held {
+        tracker.last_good  = Some(pos);
+        tracker.since_good = 0.0;
+    }
+
+    tracker.reading = reading;
+    pos
+}
+
+// Start over, eg. when a wand has been away and comes back wherever it likes
+pub fn reset (tracker: &mut Tracker) {
+    *tracker = Tracker::default();
+}
+
+
+//
+// Helpers
+//
+
+fn mirror (pos: [f32; 3]) -> [f32; 3] {
+    pos.map(|c| -c)
+}
+
+fn distance (a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;   // Reach of 100mm a tick
+
+    fn tracking_from (pos: [f32; 3]) -> Tracker {
+        let mut tracker = Tracker::default();
+        check(&mut tracker, pos, DT);
+        tracker
+    }
+
+    #[test]
+    fn a_mirrored_reading_is_a_flip () {
+        let mut tracker = tracking_from([300.0, 50.0, -200.0]);
+
+        assert_eq!(check(&mut tracker, [-305.0, -50.0, 200.0], DT), [305.0, 50.0, -200.0]);
+        assert_eq!(tracker.reading, Reading::Flipped);
+        assert_eq!(tracker.confidence, FLIP_PENALTY);
+        assert!(tracker.flipped);
+
+        // Everything after is mirrored back too, until it flips again
+        assert_eq!(check(&mut tracker, [-310.0, -50.0, 200.0], DT), [310.0, 50.0, -200.0]);
+        assert_eq!(tracker.reading, Reading::Good);
+
+        assert_eq!(check(&mut tracker, [315.0, 50.0, -200.0], DT), [315.0, 50.0, -200.0]);
+        assert_eq!(tracker.reading, Reading::Flipped);
+        assert!(!tracker.flipped);
+    }
+
+    #[test]
+    fn a_jump_is_held () {
+        let mut tracker = tracking_from([300.0, 50.0, -200.0]);
+
+        assert_eq!(check(&mut tracker, [300.0, 50.0, 400.0], DT), [300.0, 50.0, -200.0]);
+        assert_eq!(tracker.reading, Reading::Held);
+        assert_eq!(tracker.confidence, 0.0);
+
+        assert_eq!(check(&mut tracker, [f32::NAN, 50.0, -200.0], DT), [300.0, 50.0, -200.0]);
+        assert_eq!(tracker.reading, Reading::Held);
+    }
+
+    #[test]
+    fn a_jump_held_long_enough_is_believed () {
+        let mut tracker = tracking_from([300.0, 50.0, -200.0]);
+        let moved = [300.0, 50.0, 400.0];
+        let mut held_for = 0.0;
+
+        while check(&mut tracker, moved, DT) != moved {
+            assert_eq!(tracker.reading, Reading::Held);
+            held_for += DT;
+            assert!(held_for < MAX_HOLD + DT, "still held after {}s", held_for);
+        }
+
+        assert_eq!(tracker.reading, Reading::Moved);
+        assert!(held_for + DT >= MAX_HOLD - 1e-4, "believed after {}s", held_for + DT);
+
+        assert_eq!(check(&mut tracker, [300.0, 50.0, 410.0], DT), [300.0, 50.0, 410.0]);
+        assert_eq!(tracker.reading, Reading::Good);
+    }
+
+    #[test]
+    fn confidence_recovers_with_clean_readings () {
+        let mut tracker = tracking_from([300.0, 50.0, -200.0]);
+        check(&mut tracker, [300.0, 50.0, 400.0], DT);
+        assert_eq!(tracker.confidence, 0.0);
+
+        let ticks = (CONFIDENCE_RECOVERY / DT) as usize;
+        for tick in 1..=ticks {
+            check(&mut tracker, [300.0, 50.0, -200.0], DT);
+
+            if tick == ticks / 2 {
+                assert!((tracker.confidence - 0.5).abs() < 0.02, "{} halfway", tracker.confidence);
+            }
+        }
+
+        assert!(tracker.confidence > 0.99, "{} after {}s", tracker.confidence, CONFIDENCE_RECOVERY);
+        check(&mut tracker, [300.0, 50.0, -200.0], DT);
+        assert_eq!(tracker.confidence, 1.0);
+    }
+}
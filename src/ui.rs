@@ -1,6 +1,9 @@
 
-use std::io::{Write, Error};
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::io::{self, Write, Error, Stdout, stdout};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use std::f32::consts::PI;
 
 use rgb::RGB8;
@@ -11,10 +14,18 @@ use textplots::{ColorPlot,Chart,Shape};
 use drawille::{Canvas,PixelColor};
 use drawille::PixelColor::TrueColor;
 
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::IntoAlternateScreen;
+
+use crate::midi;
 use crate::midi_event::MidiEvent;
-use crate::hydra::HydraState;
-use crate::zgicabra::{DeltaEvent,Zgicabra,Wand,Hand,Direction,Joystick,NoteState,SignalState};
+use crate::hydra::{HydraState, Status};
+use crate::zgicabra::{DeltaEvent,Zgicabra,Wand,Hand,Direction,Joystick,NoteState,SignalState,Scale};
 use crate::tools::*;
+use crate::control::{Player, Snapshot, Timings, StatsReport};
+use crate::keys::KEYMAP;
+use crate::calibration::{self, Routine, Step};
+use crate::frame::{self, Frame};
 
 use crate::HISTORY_WINDOW;
 
@@ -22,6 +33,149 @@ use crate::HISTORY_WINDOW;
 type Screen = termion::screen::AlternateScreen<std::io::Stdout>;
 
 
+//
+// Terminal
+//
+// Owns the alternate screen and raw mode for as long as the UI is up. Dropping it
+// puts the terminal back how we found it, and the panic hook does the same so a
+// crash message lands on the normal screen instead of a scrambled one.
+//
+
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub struct Terminal {
+    raw:    Option<RawTerminal<Stdout>>,   // Declared first so raw mode is released before the screen
+    screen: Screen,
+}
+
+impl Terminal {
+    pub fn enter () -> io::Result<Terminal> {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+                let _ = ORIGINAL_TERMIOS.set(termios);
+            }
+        }
+
+        catch_interrupts();
+
+        let mut screen = stdout().into_alternate_screen()?;
+        write!(screen, "{}{}{}", termion::clear::All, termion::cursor::Hide, termion::cursor::Goto(1, 1))?;
+        screen.flush()?;
+
+        Ok(Terminal { raw: None, screen })
+    }
+
+    pub fn raw_mode (&mut self) {
+        if self.raw.is_none() {
+            self.raw = stdout().into_raw_mode().ok();
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop (&mut self) {
+        self.raw = None;
+        let _ = write!(self.screen, "{}{}", termion::style::Reset, termion::cursor::Show);
+        let _ = self.screen.flush();
+    }
+}
+
+// Safe to call from a panic hook: restores the saved terminal settings and leaves
+// the alternate screen without needing the Terminal itself. Does nothing if the
+// terminal was never taken over.
+pub fn restore_terminal () {
+    let Some(termios) = ORIGINAL_TERMIOS.get() else { return };
+
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios); }
+
+    let mut out = stdout();
+    let _ = write!(out, "{}{}{}", termion::style::Reset, termion::cursor::Show, termion::screen::ToMainScreen);
+    let _ = out.flush();
+}
+
+// Ctrl-C only arrives as a signal before raw mode is on (or via kill), and all we
+// do is note it. The UI loop notices and shuts down through the normal path.
+pub fn catch_interrupts () {
+    unsafe {
+        libc::signal(libc::SIGINT,  on_interrupt as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_interrupt as *const () as libc::sighandler_t);
+    }
+}
+
+pub fn interrupted () -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+extern "C" fn on_interrupt (_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+
+//
+// Layouts
+//
+// Named arrangements of panes. Every pane draws into a Rect carved out of the
+// current terminal size, so nothing overlaps however big the window is.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Performance,
+    Debug,
+    Kinematics,
+    MidiMonitor,
+}
+
+impl Layout {
+    pub fn next (self) -> Layout {
+        match self {
+            Layout::Performance => Layout::Debug,
+            Layout::Debug       => Layout::Kinematics,
+            Layout::Kinematics  => Layout::MidiMonitor,
+            Layout::MidiMonitor => Layout::Performance,
+        }
+    }
+
+    pub fn name (self) -> &'static str {
+        match self {
+            Layout::Performance => "performance",
+            Layout::Debug       => "debug",
+            Layout::Kinematics  => "kinematics",
+            Layout::MidiMonitor => "midi monitor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    pub h: u16,
+}
+
+impl Rect {
+    pub fn split_rows (self, top: u16) -> (Rect, Rect) {
+        let top = top.min(self.h);
+        (Rect { h: top, ..self }, Rect { y: self.y + top, h: self.h - top, ..self })
+    }
+
+    pub fn split_cols (self, left: u16) -> (Rect, Rect) {
+        let left = left.min(self.w);
+        (Rect { w: left, ..self }, Rect { x: self.x + left, w: self.w - left, ..self })
+    }
+}
+
+pub const MIN_SIZE: (u16, u16) = (40, 12);
+
+pub fn screen_rect () -> Rect {
+    let (w, h) = termion::terminal_size().unwrap_or((80, 24));
+    Rect { x: 1, y: 1, w: w.max(MIN_SIZE.0), h: h.max(MIN_SIZE.1) }
+}
+
+
 const BLUE_0:RGB8  = RGB8 { r: 120, g: 150, b: 255 };
 const BLUE_1:RGB8  = RGB8 { r: 150, g: 200, b: 255 };
 const BLUE_2:RGB8  = RGB8 { r:  60, g:  80, b: 155 };
@@ -37,48 +191,112 @@ const GREEN_3:RGB8 = RGB8 { r: 180, g: 180, b: 180 };
 // Main Drawing Functions
 //
 
-pub fn draw_all (zgicabra: &Zgicabra, history: &Vec<Zgicabra>) {
+// With more than one player, the performance view stacks a panel for each of
+// them, and the detail views follow whichever player has focus.
+pub fn draw_layout (frame: &mut Frame, layout: Layout, snapshot: &Snapshot, focus: usize, midi_log: &VecDeque<MidiEvent>, period: Duration) {
+    let screen = Rect { x: 1, y: 1, w: frame.w, h: frame.h };
+    let (body, footer) = screen.split_rows(screen.h - 1);
+
+    let players = &snapshot.players;
+    let focused = &players[focus.min(players.len() - 1)];
+
+    match layout {
+        Layout::Performance => {
+            let mut rest = body;
+            for (ix, player) in players.iter().enumerate() {
+                let (panel, below) = rest.split_rows(rest.h / (players.len() - ix) as u16);
+                draw_performance(frame, panel, &player.zgicabra, &player_title(ix, players.len()));
+                rest = below;
+            }
+        },
+
+        Layout::Debug => {
+            let (top, bottom)    = body.split_rows(body.h / 2);
+            let (events, right)  = bottom.split_cols(bottom.w / 2);
+            let (notes, timings) = right.split_cols(right.w / 2);
+
+            draw_performance(frame, top, &focused.zgicabra, &player_title(focus, players.len()));
+            draw_events(frame, events, &focused.delta_events, &snapshot.midi_events);
+            draw_note_state(frame, notes, &focused.zgicabra.note, &focused.zgicabra.signal, focused.zgicabra.scale);
+            draw_timings(frame, timings, &snapshot.timings, &snapshot.stats, period);
+        },
+
+        Layout::Kinematics => {
+            let (graph, readout) = body.split_rows(body.h.saturating_sub(KINEMATICS_ROWS));
+
+            draw_graph(frame, graph, &focused.history);
+            draw_kinematics(frame, readout, &focused.zgicabra);
+        },
+
+        Layout::MidiMonitor => {
+            let (log, right)     = body.split_cols(body.w / 2);
+            let (notes, timings) = right.split_rows(right.h / 2);
+
+            draw_midi_log(frame, log, midi_log);
+            draw_note_state(frame, notes, &focused.zgicabra.note, &focused.zgicabra.signal, focused.zgicabra.scale);
+            draw_timings(frame, timings, &snapshot.timings, &snapshot.stats, period);
+        },
+    }
+
+    draw_footer(frame, footer, layout, focus, players, snapshot.midi_status);
+
+    if let Some(routine) = snapshot.calibration.as_ref() {
+        draw_calibration(frame, routine, players.len() > 1);
+    }
+}
+
+fn player_title (ix: usize, players: usize) -> String {
+    if players > 1 { format!(" zgicabra {} ", ix + 1) } else { " zgicabra ".to_string() }
+}
+
+pub fn draw_performance (frame: &mut Frame, area: Rect, zgicabra: &Zgicabra, title: &str) {
 
-    // Text dimensions
-    const TEXT_WIDTH  : u16 = 76;
-    const TEXT_HEIGHT : u16 = TEXT_WIDTH / 4;
+    // Text dimensions, leaving a row each for the banner and the barcode
+    let text_width  = area.w;
+    let text_height = area.h.saturating_sub(2).max(1);
 
     // Pixel dimensions
-    const CANVAS_WIDTH  : u16 = TEXT_WIDTH * 2;
-    const CANVAS_HEIGHT : u16 = TEXT_HEIGHT * 4;
+    let canvas_width  = text_width  * 2;
+    let canvas_height = text_height * 4;
 
     // Vector dimensions
-    const WIDTH  : f32 = CANVAS_WIDTH  as f32;
-    const HEIGHT : f32 = CANVAS_HEIGHT as f32;
+    let width  = canvas_width  as f32;
+    let height = canvas_height as f32;
+    let radius = (width / 6.0).min(height / 2.0 / 1.4);
 
     // Canvas
-    let mut canvas = Canvas::new(CANVAS_WIDTH as u32, CANVAS_HEIGHT as u32);
+    let mut canvas = Canvas::new(canvas_width as u32, canvas_height as u32);
 
-    draw_banner(TEXT_WIDTH, 1, zgicabra.level == 0.0);
+    draw_banner(frame, area, title, zgicabra.level == 0.0);
+
+    // Wands are drawn where the hands holding them are, which is the other way
+    // round from their parts when mirrored
+    let (west, east) = if zgicabra.mirrored { (zgicabra.right, zgicabra.left) } else { (zgicabra.left, zgicabra.right) };
 
     if !zgicabra.docked {
-        draw_wand(&mut canvas, zgicabra.left,  WIDTH*1.0/4.0, HEIGHT/2.0, WIDTH/6.0);
-        draw_wand(&mut canvas, zgicabra.right, WIDTH*3.0/4.0, HEIGHT/2.0, WIDTH/6.0);
+        draw_wand(&mut canvas, west, width*1.0/4.0, height/2.0, radius);
+        draw_wand(&mut canvas, east, width*3.0/4.0, height/2.0, radius);
 
         if zgicabra.level > 0.0  {
             draw_bend(&mut canvas, zgicabra.separation,
-                      zgicabra.left.twist, zgicabra.right.twist,
-                      (WIDTH*1.0/4.0) as u32,
-                      (WIDTH*3.0/4.0) as u32,
-                      (HEIGHT/2.0) as u32,
-                      WIDTH/6.0,
+                      west.twist, east.twist,
+                      (width*1.0/4.0) as u32,
+                      (width*3.0/4.0) as u32,
+                      (height/2.0) as u32,
+                      radius,
                       zgicabra.level);
         }
     } else {
-        draw_wand_fixed(&mut canvas, zgicabra.left,  WIDTH*1.0/4.0, HEIGHT/2.0, WIDTH/6.0);
-        draw_wand_fixed(&mut canvas, zgicabra.right, WIDTH*3.0/4.0, HEIGHT/2.0, WIDTH/6.0);
+        draw_wand_fixed(&mut canvas, west, width*1.0/4.0, height/2.0, radius);
+        draw_wand_fixed(&mut canvas, east, width*3.0/4.0, height/2.0, radius);
     }
 
     // Output canvas
-    print!("{}{}", termion::cursor::Goto(1, 2), &mut canvas.frame());
-    print!("{}{}", termion::cursor::Goto(1, TEXT_HEIGHT + 4), barcode_string(TEXT_WIDTH.into(), zgicabra.level == 0.0));
+    let mut rows = canvas.rows();
+    rows.truncate(text_height as usize);
+    drawille_paste(frame, &rows, area.x, area.y + 1);
 
-    //print!("{}", history[0].sequence_number);
+    frame::put(frame, area.x, area.y + text_height + 1, &barcode_string(text_width.into(), zgicabra.level == 0.0));
 }
 
 
@@ -225,14 +443,13 @@ fn draw_home_button (canvas: &mut Canvas, x: f32, y: f32, radius: f32, angle: f3
 }
                      
 
-fn draw_banner (width: u16, y: u16, solid: bool) {
-    let banner_text = " zgicabra ";
-    let stripe_length = (width - banner_text.len() as u16) / 2;
+fn draw_banner (frame: &mut Frame, area: Rect, banner_text: &str, solid: bool) {
+    let stripe_length = area.w.saturating_sub(banner_text.len() as u16) / 2;
 
-    print!("{}{}{}{}", termion::cursor::Goto(1,y),
+    frame::put(frame, area.x, area.y, &format!("{}{}{}",
         barcode_string(stripe_length.into(), solid),
         banner_text,
-        barcode_string(stripe_length.into(), solid));
+        barcode_string(stripe_length.into(), solid)));
 }
 
 
@@ -334,9 +551,9 @@ fn breakup (n: f32, r: f32) -> (f32, PixelColor) {
     (j / 3.0, c)
 }
 
-fn drawille_paste (rows: &mut Vec<String>, x: u16, y: u16) {
+fn drawille_paste (frame: &mut Frame, rows: &[String], x: u16, y: u16) {
     for (ix, row) in rows.iter().enumerate() {
-        print!("{}{}", termion::cursor::Goto(x,y+ix as u16), row);
+        frame::put(frame, x, y + ix as u16, row);
     }
 }
 
@@ -374,7 +591,9 @@ fn rand_barcode_char_as_str (solid: bool) -> char {
 // Plots and Readouts
 //
 
-pub fn draw_graph (history: &Vec<Zgicabra>) {
+const KINEMATICS_ROWS: u16 = 9;
+
+pub fn draw_graph (frame: &mut Frame, area: Rect, history: &Vec<Zgicabra>) {
 
     let n = history.len();
 
@@ -393,18 +612,21 @@ pub fn draw_graph (history: &Vec<Zgicabra>) {
             Some(frame) => {
                 left_pos[i]   = (i as f32, frame.left.pos[0]);
                 right_pos[i]  = (i as f32, frame.right.pos[0]);
-                left_vel[i]   = (i as f32, frame.left.scalar_vel   *   -100.0);
-                right_vel[i]  = (i as f32, frame.right.scalar_vel  *    100.0);
-                left_acc[i]   = (i as f32, frame.left.scalar_acc   *   -800.0);
-                right_acc[i]  = (i as f32, frame.right.scalar_acc  *    800.0);
-                left_jerk[i]  = (i as f32, frame.left.scalar_jerk  * -60000.0);
-                right_jerk[i] = (i as f32, frame.right.scalar_jerk *  60000.0);
+                left_vel[i]   = (i as f32, frame.left.scalar_vel   * -100.0);
+                right_vel[i]  = (i as f32, frame.right.scalar_vel  *  100.0);
+                left_acc[i]   = (i as f32, frame.left.scalar_acc   *   -0.8);
+                right_acc[i]  = (i as f32, frame.right.scalar_acc  *    0.8);
+                left_jerk[i]  = (i as f32, frame.left.scalar_jerk  *  -0.06);
+                right_jerk[i] = (i as f32, frame.right.scalar_jerk *   0.06);
             }
         }
     }
 
-    print!("{}", termion::cursor::Goto(1, 39));
-    Chart::new_with_y_range(140, 140, 0.0, n as f32, -500.0, 500.0)
+    // textplots draws a row of labels under the plot, and refuses anything under 32 dots
+    let width  = (area.w as u32 * 2).max(32);
+    let height = (area.h.saturating_sub(1) as u32 * 4).max(32);
+
+    let chart = Chart::new_with_y_range(width, height, 0.0, n as f32, -500.0, 500.0)
         .linecolorplot(&Shape::Lines(&left_jerk), GREEN_3)
         .linecolorplot(&Shape::Lines(&left_acc),  GREEN_2)
         .linecolorplot(&Shape::Lines(&left_vel),  GREEN_1)
@@ -413,39 +635,176 @@ pub fn draw_graph (history: &Vec<Zgicabra>) {
         .linecolorplot(&Shape::Lines(&right_acc),  BLUE_2)
         .linecolorplot(&Shape::Lines(&right_vel),  BLUE_0)
         .linecolorplot(&Shape::Lines(&right_pos),  BLUE_1)
-        .display();
+        .to_string();
+
+    let rows: Vec<String> = chart.lines().take(area.h as usize).map(String::from).collect();
+    drawille_paste(frame, &rows, area.x, area.y);
 }
 
-pub fn draw_events (delta_events: &Vec<DeltaEvent>, midi_events: &Vec<MidiEvent>) {
-    print!("{}", termion::cursor::Goto(1, 26));
-    for i in 0..22 {
-        println!("                                                                                 ");
-    }
+pub fn draw_events (frame: &mut Frame, area: Rect, delta_events: &Vec<DeltaEvent>, midi_events: &Vec<MidiEvent>) {
+    let (left, right) = area.split_cols(area.w / 2);
 
-    println!("{}Delta events: {}", termion::cursor::Goto(1, 26), delta_events.len());
-    for (ix, event) in delta_events.iter().enumerate() {
-        println!("{}- {:?}", termion::cursor::Goto(1, 28 + ix as u16), event);
-    }
+    let mut deltas = vec![format!("Delta events: {}", delta_events.len()), String::new()];
+    deltas.extend(delta_events.iter().map(|event| format!("- {:?}", event)));
+
+    let mut midi = vec![format!("MIDI events:  {}", midi_events.len()), String::new()];
+    midi.extend(midi_events.iter().map(|event| format!("- {:?}", event)));
+
+    print_lines(frame, left,  &deltas);
+    print_lines(frame, right, &midi);
+}
+
+pub fn draw_note_state (frame: &mut Frame, area: Rect, note_state: &NoteState, signal_state: &SignalState, scale: Scale) {
+    print_lines(frame, area, &[
+        format!("Note: [{}]", if note_state.on { note_state.current } else { 0 }),
+        String::new(),
+        format!("- Root:    {}", format_note(note_state.root)),
+        format!("- Current: {}", format_note(note_state.current)),
+        format!("- Pitch:   {}", note_state.bend),
+        format!("- Scale:   {:?}", scale),
+        String::new(),
+        "Signals:".to_string(),
+        String::new(),
+        format!("- Filter: {}", signal_state.filter),
+        format!("- Fuzz:   {}", signal_state.fuzz),
+        format!("- Width:  {}", signal_state.width),
+        format!("- Thump:  {}", signal_state.thump),
+        format!("- |vel|:  {}", signal_state.velocity),
+        format!("- |acc|:  {}", signal_state.acceleration),
+        format!("- |jrk|:  {}", signal_state.jerk),
+    ]);
+}
+
+pub fn draw_timings (frame: &mut Frame, area: Rect, timings: &Timings, stats: &StatsReport, period: Duration) {
+    fn ms (d: Duration) -> f32 { d.as_secs_f32() * 1000.0 }
+
+    print_lines(frame, area, &[
+        "Timing (ms):".to_string(),
+        String::new(),
+        format!("- sensors {:6.2}", ms(timings.sensors)),
+        format!("- gesture {:6.2}", ms(timings.gesture)),
+        format!("- midi    {:6.2}", ms(timings.midi)),
+        format!("- extras  {:6.2}", ms(timings.extras)),
+        format!("- total   {:6.2} / {:.0}", ms(timings.total), ms(period)),
+        format!("- latency {:6.2}", ms(timings.latency)),
+        String::new(),
+        "Loop period:".to_string(),
+        format!("  {}", stats.period),
+        "Input-to-MIDI:".to_string(),
+        format!("  {}", stats.latency),
+        format!("Overruns: {}", stats.overruns),
+    ]);
+}
 
-    println!("{}MIDI events:  {}", termion::cursor::Goto(29, 26), midi_events.len());
-    for (ix, event) in midi_events.iter().enumerate() {
-        println!("{}- {:?}", termion::cursor::Goto(30, 28 + ix as u16), event);
+pub fn draw_kinematics (frame: &mut Frame, area: Rect, zgicabra: &Zgicabra) {
+    fn v3 (v: [f32; 3]) -> String { format!("{:8.3} {:8.3} {:8.3}", v[0], v[1], v[2]) }
+
+    let geometry = &zgicabra.geometry;
+
+    print_lines(frame, area, &[
+        format!("{:6} {:28} {:28}", "", "left", "right"),
+        format!("{:6} {:28} {:28}", "pos",  v3(zgicabra.left.pos),  v3(zgicabra.right.pos)),
+        format!("{:6} {:28} {:28}", "vel",  v3(zgicabra.left.vel),  v3(zgicabra.right.vel)),
+        format!("{:6} {:28} {:28}", "acc",  v3(zgicabra.left.acc),  v3(zgicabra.right.acc)),
+        format!("{:6} {:28} {:28}", "jerk", v3(zgicabra.left.jerk), v3(zgicabra.right.jerk)),
+        format!("{:6} {:28} {:28}", "angvel", v3(zgicabra.left.ang_vel), v3(zgicabra.right.ang_vel)),
+        format!("{:6} {:<28.2} {:<28.2}", "track", zgicabra.left.tracking, zgicabra.right.tracking),
+        format!("{:6} {:8.3} pitch {:8.3}/{:8.3} yaw {:8.3}/{:8.3} twist {:8.3}/{:8.3}", "sep",
+            zgicabra.separation, zgicabra.left.pitch, zgicabra.right.pitch, zgicabra.left.yaw, zgicabra.right.yaw,
+            zgicabra.left.twist, zgicabra.right.twist),
+        format!("{:6} {:8.3} rel {} height {:8.3} angle {:8.3} mid {}", "dist",
+            geometry.distance, v3(geometry.relative), geometry.height, geometry.angle, v3(geometry.midpoint)),
+    ]);
+}
+
+pub fn draw_midi_log (frame: &mut Frame, area: Rect, midi_log: &VecDeque<MidiEvent>) {
+    let visible = area.h.saturating_sub(2) as usize;
+
+    let mut lines = vec![format!("MIDI monitor: last {}", midi_log.len()), String::new()];
+    lines.extend(midi_log.iter().rev().take(visible).map(|event| format!("- {:?}", event)));
+
+    print_lines(frame, area, &lines);
+}
+
+pub fn draw_help (frame: &mut Frame) {
+    let x = (frame.w.saturating_sub(36) / 2).max(1);
+    let y = 3;
+
+    frame::put(frame, x, y,     "┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓");
+    frame::put(frame, x, y + 1, "┃ Keys                             ┃");
+
+    for (ix, binding) in KEYMAP.iter().enumerate() {
+        frame::put(frame, x, y + 2 + ix as u16, &format!("┃ {:>6}  {:<24} ┃", binding.label, binding.description));
     }
+
+    frame::put(frame, x, y + 2 + KEYMAP.len() as u16, "┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛");
+}
+
+// Overlays the current calibration step, with what's been captured so far
+pub fn draw_calibration (frame: &mut Frame, routine: &Routine, name_player: bool) {
+    let x = (frame.w.saturating_sub(62) / 2).max(1);
+    let y = 3;
+
+    let title = if name_player { format!("Calibrating player {}", routine.player + 1) } else { "Calibrating".to_string() };
+    let step  = match routine.step { Step::Rest => 1, Step::Twist => 2, Step::Trigger => 3 };
+    let [left, right] = routine.result;
+
+    let progress = match routine.step {
+        Step::Rest    => {
+            let [left, right] = calibration::rest_samples(routine);
+            format!("rest samples   L {:6}   R {:6}", left, right)
+        },
+        Step::Twist   => format!("twist range    L {:6.3}   R {:6.3}", left.twist_range, right.twist_range),
+        Step::Trigger => format!("trigger max    L {:6.3}   R {:6.3}", left.trigger_max, right.trigger_max),
+    };
+
+    frame::put(frame, x, y,     "┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓");
+    frame::put(frame, x, y + 1, &format!("┃ {:<49} step {}/3 ┃", title, step));
+    frame::put(frame, x, y + 2, &format!("┃ {:<58} ┃", ""));
+    frame::put(frame, x, y + 3, &format!("┃ {:<58} ┃", routine.step.instructions()));
+    frame::put(frame, x, y + 4, &format!("┃ {:<58} ┃", progress));
+    frame::put(frame, x, y + 5, &format!("┃ {:<58} ┃", ""));
+    frame::put(frame, x, y + 6, &format!("┃ {:<58} ┃", "Enter when done, c to cancel"));
+    frame::put(frame, x, y + 7, "┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛");
 }
 
-pub fn draw_note_state (note_state: &NoteState, signal_state: &SignalState) {
-    println!("{}Note: [{}]", termion::cursor::Goto(58, 26), if note_state.on { note_state.current } else { 0 });
-    println!("{}- Root:    {}", termion::cursor::Goto(58, 28), format_note(note_state.root));
-    println!("{}- Current: {}", termion::cursor::Goto(58, 29), format_note(note_state.current));
-    println!("{}- Pitch:   {}", termion::cursor::Goto(58, 30), note_state.bend);
+fn draw_footer (frame: &mut Frame, area: Rect, layout: Layout, focus: usize, players: &[Player], midi_status: midi::Status) {
+    fn hydra (status: Status) -> &'static str {
+        match status {
+            Status::Ready                 => "ok",
+            Status::OneWand               => "ONE WAND",
+            Status::WaitingForControllers => "WAITING FOR WANDS",
+            Status::NoBase                => "NO BASE",
+        }
+    }
+
+    let (view, hydra) = if players.len() > 1 {
+        let statuses: Vec<String> = players.iter().enumerate()
+            .map(|(ix, player)| format!("P{} {}", ix + 1, hydra(player.status)))
+            .collect();
+        (format!("{} · P{}", layout.name(), focus + 1), statuses.join("  "))
+    } else {
+        (layout.name().to_string(), format!("Hydra {}", hydra(players[0].status)))
+    };
+
+    let midi = match midi_status {
+        midi::Status::Connected            => "MIDI ok".to_string(),
+        midi::Status::Lost { attempts: 0 } => "MIDI LOST".to_string(),
+        midi::Status::Lost { attempts }    => format!("MIDI LOST, retried {}", attempts),
+    };
 
-    println!("{}Signals:", termion::cursor::Goto(58, 32));
-    println!("{}- Filter: {}", termion::cursor::Goto(58, 34), signal_state.filter);
-    println!("{}- Fuzz:   {}", termion::cursor::Goto(58, 35), signal_state.fuzz);
-    println!("{}- Width:  {}", termion::cursor::Goto(58, 36), signal_state.width);
-    println!("{}- Thump:  {}", termion::cursor::Goto(58, 37), signal_state.thump);
-    println!("{}- |vel|:  {}", termion::cursor::Goto(58, 38), signal_state.velocity);
-    println!("{}- |acc|:  {}", termion::cursor::Goto(58, 39), signal_state.acceleration);
-    println!("{}- |jrk|:  {}", termion::cursor::Goto(58, 40), signal_state.jerk);
+    print_lines(frame, area, &[format!("[{}]  {}  {}  1-4/Tab switch view  ? help", view, hydra, midi)]);
 }
 
+
+//
+// Text Helpers
+//
+
+// Puts each line into the area, one per row. Anything that doesn't fit is cut.
+fn print_lines<S: AsRef<str>> (frame: &mut Frame, area: Rect, lines: &[S]) {
+    for (row, line) in lines.iter().take(area.h as usize).enumerate() {
+        let text: String = line.as_ref().chars().take(area.w as usize).collect();
+        frame::put(frame, area.x, area.y + row as u16, &text);
+    }
+}
@@ -4,18 +4,34 @@ use std::time::Duration;
 use std::io::{Error};
 use core::f32::consts::PI;
 
+use serde::Serialize;
+
 use crate::hydra;
 use crate::hydra::{HydraState,ControllerFrame};
+use crate::calibration;
+use crate::calibration::Calibration;
+use crate::orientation;
+use crate::orientation::Order;
+use crate::filter;
+use crate::filter::Filter;
+use crate::space;
+use crate::space::Space;
+use crate::tracking;
+use crate::tracking::Tracker;
 use crate::tools::*;
 
 const JOYSTICK_DEADZONE: f32 = 0.15;
+const SAMPLE_PERIOD:     f32 = 1.0 / 60.0;   // The Hydra reports at 60Hz
+const MIN_TIMEDELTA:     f32 = 1.0e-4;       // Any closer than this and the clock's no use
+const RECENTER_HOLD:     f32 = 1.0;          // Seconds holding both Home buttons
+const MIN_TRACKING:      f32 = 0.25;         // Tracking confidence below which position, bend and notes hold
 
 
 //
 // Data Types
 //
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum Voice {
     Classic    = 0,
     Eternal    = 1,
@@ -23,14 +39,65 @@ pub enum Voice {
     Submission = 3
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl Voice {
+    pub fn next (self) -> Voice {
+        match self {
+            Voice::Classic    => Voice::Eternal,
+            Voice::Eternal    => Voice::Pennysack,
+            Voice::Pennysack  => Voice::Submission,
+            Voice::Submission => Voice::Classic,
+        }
+    }
+
+    pub fn prev (self) -> Voice {
+        self.next().next().next()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Scale {
+    Minor,
+    Major,
+    Dorian,
+    Phrygian,
+    HarmonicMinor,
+    MinorPentatonic,
+}
+
+impl Scale {
+    // Seven steps up from the root. The left stick plays these clockwise from Up,
+    // and Left/UpLeft reach down an octave for the top two.
+    pub fn degrees (self) -> [i8; 7] {
+        match self {
+            Scale::Minor           => [0, 2, 3, 5, 7, 8, 10],
+            Scale::Major           => [0, 2, 4, 5, 7, 9, 11],
+            Scale::Dorian          => [0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian        => [0, 1, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor   => [0, 2, 3, 5, 7, 8, 11],
+            Scale::MinorPentatonic => [0, 3, 5, 7, 10, 12, 15],
+        }
+    }
+
+    pub fn next (self) -> Scale {
+        match self {
+            Scale::Minor           => Scale::Major,
+            Scale::Major           => Scale::Dorian,
+            Scale::Dorian          => Scale::Phrygian,
+            Scale::Phrygian        => Scale::HarmonicMinor,
+            Scale::HarmonicMinor   => Scale::MinorPentatonic,
+            Scale::MinorPentatonic => Scale::Minor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum Hand {
     Neither,
     Left,
     Right,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum Direction {
     None,
     Left,
@@ -60,7 +127,7 @@ impl PartialEq for Direction {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct Joystick {
     pub x: f32,
     pub y: f32,
@@ -85,19 +152,22 @@ impl Joystick {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct Wand {
     pub pos: [f32; 3],
     pub rot: [f32; 4],
     pub vel: [f32; 3],
     pub acc: [f32; 3],
     pub jerk: [f32; 3],
+    pub ang_vel: [f32; 3],
     pub pitch: f32,
+    pub yaw: f32,
     pub twist: f32,
     pub scalar_vel: f32,
     pub scalar_acc: f32,
     pub scalar_jerk: f32,
     pub trigger: f32,
+    pub tracking: f32,          // Confidence in the position, 0 to 1 (see tracking.rs)
     pub bumper: bool,
     pub home: bool,
     pub buttons: [bool; 4],
@@ -113,12 +183,15 @@ impl Wand {
             vel: [0.0, 0.0, 0.0],
             acc: [0.0, 0.0, 0.0],
             jerk: [0.0, 0.0, 0.0],
+            ang_vel: [0.0, 0.0, 0.0],
             pitch: 0.0,
+            yaw: 0.0,
             twist: 0.0,
             scalar_vel: 0.0,
             scalar_acc: 0.0,
             scalar_jerk: 0.0,
             trigger: 0.0,
+            tracking: 1.0,
             bumper: false,
             home: false,
             buttons: [false, false, false, false],
@@ -128,7 +201,7 @@ impl Wand {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct NoteState {
     pub on: bool,
     pub root: u8,
@@ -147,7 +220,7 @@ impl NoteState {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct SignalState {
     pub filter:       f32,
     pub fuzz:         f32,
@@ -172,6 +245,17 @@ impl SignalState {
     }
 }
 
+// Where the wands are relative to each other. Positions are in millimetres,
+// angles in radians, and "right" and "left" are the parts being played.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Geometry {
+    pub distance: f32,          // Straight line between the wands
+    pub relative: [f32; 3],     // Right wand as seen from the left
+    pub height:   f32,          // How far the right wand is above the left
+    pub angle:    f32,          // Between the directions they point, 0 to π
+    pub midpoint: [f32; 3],
+}
+
 
 //
 // Delta Events
@@ -179,7 +263,7 @@ impl SignalState {
 
 type Note  = u8;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum DeltaEvent {
     NoteStart(Note),
     NoteChange(Note, Note),
@@ -189,10 +273,14 @@ pub enum DeltaEvent {
     WidthLevel(f32),
     PitchBend(f32),
     VoiceChange(Voice),
+    Recenter(),
     TuneUp(),
     TuneDown(),
+    OctaveUp(),
+    OctaveDown(),
     NextVoice(),
     PrevVoice(),
+    NextScale(),
     ThumpToggle(),
     FuzzToggle(),
     Panic()
@@ -203,17 +291,28 @@ pub enum DeltaEvent {
 // Main Datatype
 //
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Zgicabra {
     pub left:  Wand,
     pub right: Wand,
-    pub separation: f32,
+    pub separation: f32,        // Across only, see geometry for the rest
+    pub geometry: Geometry,
     pub docked: bool,
     pub level: f32,
     pub sequence_number: u8,
     pub note: NoteState,
     pub signal: SignalState,
     pub voice: Voice,
+    pub scale: Scale,
+    pub one_hand: Hand,         // Neither when both hands are playing
+    pub mirrored: bool,         // Left-handed: the right wand plays the left's part
+    #[serde(skip)]
+    pub calibration: [Calibration; 2],   // By the hand holding the wand, not its part
+    #[serde(skip)]
+    pub rotation_order: Order,
+    pub neutral_twist: f32,     // Twist that means no bend when playing one-handed
+    pub space: Space,           // Where the player's standing, see space.rs
+    pub home_held: f32,         // Seconds both Home buttons have been held
 }
 
 impl Zgicabra {
@@ -222,60 +321,147 @@ impl Zgicabra {
             left:  Wand::new(),
             right: Wand::new(),
             separation: 0.0,
+            geometry: Geometry::default(),
             docked: false,
             level: 0.0,
             sequence_number: 0,
             note: NoteState::new(),
             signal: SignalState::new(),
             voice: Voice::Classic,
+            scale: Scale::Minor,
+            one_hand: Hand::Neither,
+            mirrored: false,
+            calibration: [Calibration::default(); 2],
+            rotation_order: Order::default(),
+            neutral_twist: 0.0,
+            space: Space::default(),
+            home_held: 0.0,
+        }
+    }
+}
+
+// What's behind each wand's readings: the glitch trackers, by hand, and the
+// motion filters, by part. It's kept out of Zgicabra, which is cloned into the
+// history and for the UI every tick, and passed in alongside it instead.
+#[derive(Debug, Clone)]
+pub struct Filtering {
+    pub filters:  [Filter; 2],
+    pub trackers: [Tracker; 2],
+}
+
+impl Filtering {
+    pub fn new (settings: filter::Settings) -> Filtering {
+        Filtering {
+            filters:  [Filter::new(settings), Filter::new(settings)],
+            trackers: [Tracker::default(); 2],
         }
     }
 }
 
+impl Default for Filtering {
+    fn default () -> Filtering {
+        Filtering::new(filter::Settings::default())
+    }
+}
+
 
 //
 // Module Functions
 //
 
-pub fn update (curr_state: &mut Zgicabra, prev_state: &Zgicabra, hydra_state: &HydraState, deltas: &mut Vec<DeltaEvent>) {
+// One player's instrument, from their pair of wands (see hydra::wands)
+
+pub fn update (curr_state: &mut Zgicabra, prev_state: &Zgicabra, filtering: &mut Filtering, left: &ControllerFrame, right: &ControllerFrame, timedelta: Duration, deltas: &mut Vec<DeltaEvent>) {
 
     // Sequence number happens always
 
-    curr_state.sequence_number = hydra_state.controllers[0].sequence_number;
-    curr_state.docked = hydra_state.controllers[0].is_docked != 0
-                     || hydra_state.controllers[1].is_docked != 0;
+    curr_state.sequence_number = left.sequence_number;
+    curr_state.docked = left.is_docked != 0
+                     || right.is_docked != 0;
+
+
+    curr_state.one_hand = Hand::Neither;
+
+    let dt = seconds_between(timedelta, curr_state.sequence_number, prev_state.sequence_number);
+
+    let left  = &checked(left,  filtering, curr_state.mirrored, dt);
+    let right = &checked(right, filtering, curr_state.mirrored, dt);
+
+
+    // Holding Home on both wands makes wherever the player is now the middle of
+    // the playing space. It takes over from the calibrated origin, and the filters
+    // start over so the jump doesn't read as a flick. Nor does the turn: angular
+    // velocity holds for that one tick.
+
+    let both_home = button_mask(left.buttons, hydra::BUTTON_HOME) && button_mask(right.buttons, hydra::BUTTON_HOME);
+    curr_state.home_held = if both_home { prev_state.home_held + dt } else { 0.0 };
+
+    let recentered = curr_state.home_held >= RECENTER_HOLD && prev_state.home_held < RECENTER_HOLD;
+
+    if recentered {
+        curr_state.space = space::capture(left, right);
+
+        for calibration in curr_state.calibration.iter_mut() {
+            calibration.origin = [0.0, 0.0, 0.0];
+        }
+
+        for filter in filtering.filters.iter_mut() {
+            filter::reset(filter);
+        }
+
+        deltas.push(DeltaEvent::Recenter());
+    }
+
+    let left  = &space::apply(&curr_state.space, left);
+    let right = &space::apply(&curr_state.space, right);
 
 
-    // Map immediately updated values (and avg position with previous frame)
+    // Mirrored, each wand takes the other's part. From here on `left` and `right`
+    // are the parts being played, not the hands playing them.
 
-    copy_frame_to_wand(&hydra_state.controllers[0], &mut curr_state.left,  &prev_state.left);
-    copy_frame_to_wand(&hydra_state.controllers[1], &mut curr_state.right, &prev_state.right);
+    let (left, right) = if curr_state.mirrored { (right, left) } else { (left, right) };
 
 
-    // Time derivatives
+    // Map immediately updated values, and their time derivatives
 
-    let dt:f32 = hydra_state.timedelta.as_millis() as f32;
+    let order = curr_state.rotation_order;
+    let [left_filter, right_filter] = &mut filtering.filters;
 
-    curr_state.left.vel   = derivative_r3(&curr_state.left.pos,  &prev_state.left.pos,  dt);
-    curr_state.right.vel  = derivative_r3(&curr_state.right.pos, &prev_state.right.pos, dt);
-    curr_state.left.acc   = derivative_r3(&curr_state.left.vel,  &prev_state.left.vel,  dt);
-    curr_state.right.acc  = derivative_r3(&curr_state.right.vel, &prev_state.right.vel, dt);
-    curr_state.left.jerk  = derivative_r3(&curr_state.left.acc,  &prev_state.left.acc,  dt);
-    curr_state.right.jerk = derivative_r3(&curr_state.right.acc, &prev_state.right.acc, dt);
+    track_wand(left,  &mut curr_state.left,  &prev_state.left,  dt, &curr_state.calibration, order, left_filter);
+    track_wand(right, &mut curr_state.right, &prev_state.right, dt, &curr_state.calibration, order, right_filter);
 
-    curr_state.left.scalar_vel   = (hyp(&curr_state.left.vel)   + &prev_state.left.scalar_vel)   / 2.0;
-    curr_state.right.scalar_vel  = (hyp(&curr_state.right.vel)  + &prev_state.right.scalar_vel)  / 2.0;
-    curr_state.left.scalar_acc   = (hyp(&curr_state.left.acc)   + &prev_state.left.scalar_acc)   / 2.0;
-    curr_state.right.scalar_acc  = (hyp(&curr_state.right.acc)  + &prev_state.right.scalar_acc)  / 2.0;
-    curr_state.left.scalar_jerk  = (hyp(&curr_state.left.jerk)  + &prev_state.left.scalar_jerk)  / 2.0;
-    curr_state.right.scalar_jerk = (hyp(&curr_state.right.jerk) + &prev_state.right.scalar_jerk) / 2.0;
+    curr_state.left.tracking  = confidence(&filtering.trackers, left);
+    curr_state.right.tracking = confidence(&filtering.trackers, right);
 
+    if curr_state.mirrored {
+        mirror_wand(&mut curr_state.left);
+        mirror_wand(&mut curr_state.right);
+    }
+
+    if recentered {
+        curr_state.left.ang_vel  = prev_state.left.ang_vel;
+        curr_state.right.ang_vel = prev_state.right.ang_vel;
+    }
 
-    // Two-handed values
 
-    curr_state.separation = (curr_state.left.pos[0] - curr_state.right.pos[0]).abs();
-    curr_state.note.bend  = curr_state.left.twist - curr_state.right.twist;
-    curr_state.note.bend  = curr_state.note.bend.powf(3.0).clamp(-2.0, 2.0) * 0.5;
+    // Two-handed values. While either wand's tracking is shaky (see tracking.rs)
+    // everything that comes from where the wands are or how they're turned holds
+    // where it was, rather than follow a glitch: separation and geometry (and so
+    // the mapped CCs), bend, and the note the sticks pick. Notes still start and
+    // end with the triggers, which aren't tracked magnetically and can't glitch;
+    // holding them too would only swallow a real note-off.
+
+    let shaky = curr_state.left.tracking < MIN_TRACKING || curr_state.right.tracking < MIN_TRACKING;
+
+    if shaky {
+        curr_state.separation = prev_state.separation;
+        curr_state.geometry   = prev_state.geometry;
+        curr_state.note.bend  = prev_state.note.bend;
+    } else {
+        curr_state.separation = (curr_state.left.pos[0] - curr_state.right.pos[0]).abs();
+        curr_state.geometry   = two_hand_geometry(&curr_state.left, &curr_state.right, curr_state.mirrored);
+        curr_state.note.bend  = bend_curve(curr_state.left.twist - curr_state.right.twist);
+    }
 
     let trigger_total = curr_state.left.trigger + curr_state.right.trigger;
     curr_state.level  = smoothstep(0.0, 1.0, trigger_total.clamp(0.0, 1.0));
@@ -307,11 +493,11 @@ pub fn update (curr_state: &mut Zgicabra, prev_state: &Zgicabra, hydra_state: &H
     }
 
     // Update note to stick position
-    let new_note = (curr_state.note.root as i8
-        + stick_to_note_offset(&curr_state.left)
-        + stick_to_note_modifier(&curr_state.right)) as u8;
+    let new_note = note_from(curr_state.note.root,
+        stick_to_note_offset(&curr_state.left, curr_state.scale),
+        stick_to_note_modifier(&curr_state.right));
 
-    if curr_state.note.current != new_note && curr_state.note.on {
+    if curr_state.note.current != new_note && curr_state.note.on && !shaky {
         deltas.push(DeltaEvent::NoteChange(curr_state.note.current, new_note));
         curr_state.note.current = new_note;
     }
@@ -367,8 +553,8 @@ pub fn update (curr_state: &mut Zgicabra, prev_state: &Zgicabra, hydra_state: &H
             let rock_direction:i8 = if !prev_state.left.buttons[i] { -1 } else { 1 };
 
             match i {
-                0 => curr_state.note.root = curr_state.note.root + 1,
-                1 => curr_state.note.root = ((curr_state.note.root as i8) + rock_direction) as u8,
+                0 => curr_state.note.root = (curr_state.note.root + 1).min(127),
+                1 => curr_state.note.root = (curr_state.note.root as i16 + rock_direction as i16).clamp(0, 127) as u8,
                 _ => {},
             }
         }
@@ -392,21 +578,272 @@ pub fn update (curr_state: &mut Zgicabra, prev_state: &Zgicabra, hydra_state: &H
 
 
 
+// Single-wand play, for when the other wand is flat or unplugged, or for anyone
+// playing with one hand. The one wand does everything:
+//
+//   stick    - note, from the scale as the left stick plays it
+//   1 / 2    - octave up / down
+//   twist    - bend, relative to the neutral twist
+//   bumper   - take the current twist as neutral
+//   trigger  - note on/off and level
+//
+// Neutral is also taken whenever one-handed play begins, so the wand doesn't
+// start out bent.
+
+pub fn update_one_handed (curr_state: &mut Zgicabra, prev_state: &Zgicabra, filtering: &mut Filtering, frame: &ControllerFrame, hand: Hand, timedelta: Duration, deltas: &mut Vec<DeltaEvent>) {
+
+    curr_state.sequence_number = frame.sequence_number;
+    curr_state.docked = frame.is_docked != 0;
+    curr_state.home_held = 0.0;
+
+
+    // The wand we have, tracked on the side it plays. The missing one rests.
+
+    let dt = seconds_between(timedelta, curr_state.sequence_number, prev_state.sequence_number);
+
+    let frame = &space::apply(&curr_state.space, &checked(frame, filtering, curr_state.mirrored, dt));
+
+    let side = match (hand, curr_state.mirrored) {
+        (Hand::Left, false) | (Hand::Right, true) => Hand::Left,
+        _ => Hand::Right,
+    };
+
+    let prev = if side == Hand::Left { prev_state.left } else { prev_state.right };
+    let mut wand = Wand::new();
+
+    let slot = if side == Hand::Left { 0 } else { 1 };
+
+    track_wand(frame, &mut wand, &prev, dt, &curr_state.calibration, curr_state.rotation_order, &mut filtering.filters[slot]);
+    wand.tracking = confidence(&filtering.trackers, frame);
+
+    if curr_state.mirrored {
+        mirror_wand(&mut wand);
+    }
+
+    if side == Hand::Left {
+        curr_state.left  = wand;
+        curr_state.right = Wand::new();
+    } else {
+        curr_state.left  = Wand::new();
+        curr_state.right = wand;
+    }
+
+    if curr_state.one_hand != hand || (wand.bumper && !prev.bumper) {
+        curr_state.neutral_twist = wand.twist;
+    }
+
+    // Shaky tracking holds bend and the note, but not the trigger, as with two hands
+    let shaky = wand.tracking < MIN_TRACKING;
+
+    curr_state.one_hand   = hand;
+    curr_state.separation = 0.0;
+    curr_state.geometry   = Geometry::default();
+    curr_state.note.bend  = if shaky { prev_state.note.bend } else { bend_curve(wand.twist - curr_state.neutral_twist) };
+    curr_state.level      = smoothstep(0.0, 1.0, wand.trigger.clamp(0.0, 1.0));
+
+
+    // Trigger and note
+
+    let trigger_start = wand.trigger > prev.trigger && prev.trigger == 0.0;
+    let trigger_end   = prev.trigger > wand.trigger && wand.trigger == 0.0;
+
+    if trigger_start && !curr_state.note.on {
+        deltas.push(DeltaEvent::NoteStart(curr_state.note.current));
+        curr_state.note.on = true;
+    }
+
+    if trigger_end && curr_state.note.on {
+        deltas.push(DeltaEvent::NoteEnd(curr_state.note.current));
+        curr_state.note.on = false;
+    }
+
+
+    // Octave buttons, before the note so a held note follows
+
+    if wand.buttons[0] && !prev.buttons[0] {
+        apply_command(curr_state, DeltaEvent::OctaveUp(), deltas);
+    }
+
+    if wand.buttons[1] && !prev.buttons[1] {
+        apply_command(curr_state, DeltaEvent::OctaveDown(), deltas);
+    }
+
+    let new_note = note_from(curr_state.note.root, stick_to_note_offset(&wand, curr_state.scale), 0);
+
+    if curr_state.note.current != new_note && curr_state.note.on && !shaky {
+        deltas.push(DeltaEvent::NoteChange(curr_state.note.current, new_note));
+        curr_state.note.current = new_note;
+    }
+}
+
+
+
+// The sensors dropped out. Let go of anything held so it doesn't hang while we
+// wait, and forget the triggers so one still held afterwards starts a fresh note.
+
+pub fn release (state: &mut Zgicabra, filtering: &mut Filtering, deltas: &mut Vec<DeltaEvent>) {
+    if state.note.on {
+        deltas.push(DeltaEvent::NoteEnd(state.note.current));
+        state.note.on = false;
+    }
+
+    state.left.trigger  = 0.0;
+    state.right.trigger = 0.0;
+    state.level         = 0.0;
+
+    for filter in filtering.filters.iter_mut() {
+        filter::reset(filter);
+    }
+
+    for tracker in filtering.trackers.iter_mut() {
+        tracking::reset(tracker);
+    }
+}
+
+
+
+// Commands arrive from outside the gesture system (eg. the keyboard) as the same
+// DeltaEvents the wands would raise. Apply their effect and pass them on for MIDI.
+
+pub fn apply_command (state: &mut Zgicabra, command: DeltaEvent, deltas: &mut Vec<DeltaEvent>) {
+    match command {
+        DeltaEvent::TuneUp()     => state.note.root = state.note.root.saturating_add(1).min(127),
+        DeltaEvent::TuneDown()   => state.note.root = state.note.root.saturating_sub(1),
+        DeltaEvent::OctaveUp()   => state.note.root = state.note.root.saturating_add(12).min(127),
+        DeltaEvent::OctaveDown() => state.note.root = state.note.root.saturating_sub(12),
+        DeltaEvent::NextScale()  => state.scale = state.scale.next(),
+        DeltaEvent::NextVoice()  => {
+            state.voice = state.voice.next();
+            deltas.push(DeltaEvent::VoiceChange(state.voice));
+        },
+        DeltaEvent::PrevVoice()  => {
+            state.voice = state.voice.prev();
+            deltas.push(DeltaEvent::VoiceChange(state.voice));
+        },
+        _ => {},
+    }
+
+    deltas.push(command);
+}
+
+
 //
 // Helpers
 //
 
-fn copy_frame_to_wand (frame: &ControllerFrame, wand: &mut Wand, prev_wand: &Wand) {
-    wand.pos = frame.pos.clone();
-    wand.rot = frame.rot_quat.clone();
+// Seconds between this frame and the last. The clock's good to well under a
+// millisecond, but two ticks can land closer than that; then the sensors' own
+// sequence number says how many samples have gone by, and none means there's
+// nothing new, which the filter takes as no time at all.
+fn seconds_between (timedelta: Duration, sequence: u8, prev_sequence: u8) -> f32 {
+    let seconds = timedelta.as_secs_f32();
+
+    if seconds >= MIN_TIMEDELTA {
+        seconds
+    } else {
+        sequence.wrapping_sub(prev_sequence) as f32 * SAMPLE_PERIOD
+    }
+}
+
+// A raw frame with its position checked for flips and glitches (see tracking.rs).
+// This has to happen in the base's coordinates, before anything moves them. A
+// wand that really has moved after being held starts its filter over, or the
+// jump would read as a flick. Trackers go by hand and filters by part, so
+// mirrored the left hand's filter is the right one.
+fn checked (frame: &ControllerFrame, filtering: &mut Filtering, mirrored: bool, dt: f32) -> ControllerFrame {
+    let Some(hand) = hydra::hand_index(frame.which_hand) else {
+        return *frame;
+    };
+
+    let pos  = tracking::check(&mut filtering.trackers[hand], frame.pos, dt);
+    let part = if mirrored { 1 - hand } else { hand };
+
+    if filtering.trackers[hand].reading == tracking::Reading::Moved {
+        filter::reset(&mut filtering.filters[part]);
+    }
+
+    ControllerFrame { pos, ..*frame }
+}
+
+fn confidence (trackers: &[Tracker; 2], frame: &ControllerFrame) -> f32 {
+    hydra::hand_index(frame.which_hand).map(|hand| trackers[hand].confidence).unwrap_or(0.0)
+}
+
+// Fresh readings for one wand, then its motion through the filter. Velocity,
+// acceleration and jerk are in m/s, m/s² and m/s³, angular velocity in rad/s.
+fn track_wand (frame: &ControllerFrame, wand: &mut Wand, prev_wand: &Wand, dt: f32, calibration: &[Calibration; 2], order: Order, filter: &mut Filter) {
+    copy_frame_to_wand(frame, wand, prev_wand, &calibration::for_frame(calibration, frame), order);
+
+    let motion = filter::step(filter, wand.pos, dt);
 
-    wand.pitch   = frame.rot_quat[1];
-    wand.twist   = frame.rot_quat[2] * 2.0;
-    wand.trigger = frame.trigger;
+    wand.pos  = motion.pos;
+    wand.vel  = motion.vel;
+    wand.acc  = motion.acc;
+    wand.jerk = motion.jerk;
 
-    wand.pos[0] = (wand.pos[0] + prev_wand.pos[0])/2.0;
-    wand.pos[1] = (wand.pos[1] + prev_wand.pos[1])/2.0;
-    wand.pos[2] = (wand.pos[2] + prev_wand.pos[2])/2.0;
+    wand.scalar_vel  = (hyp(&wand.vel)  + prev_wand.scalar_vel)  / 2.0;
+    wand.scalar_acc  = (hyp(&wand.acc)  + prev_wand.scalar_acc)  / 2.0;
+    wand.scalar_jerk = (hyp(&wand.jerk) + prev_wand.scalar_jerk) / 2.0;
+
+    wand.ang_vel = orientation::angular_velocity(wand.rot, prev_wand.rot, dt);
+
+    // The filter keeps its own state clean, but a bad reading still shouldn't
+    // reach the outputs
+    wand.ang_vel     = wand.ang_vel.map(|w| finite_or(w, 0.0));
+    wand.scalar_vel  = finite_or(wand.scalar_vel,  0.0);
+    wand.scalar_acc  = finite_or(wand.scalar_acc,  0.0);
+    wand.scalar_jerk = finite_or(wand.scalar_jerk, 0.0);
+}
+
+fn finite_or (value: f32, fallback: f32) -> f32 {
+    if value.is_finite() { value } else { fallback }
+}
+
+// Mirrored, the right part is played on the left, so across is flipped to keep
+// hands apart meaning the same thing
+fn two_hand_geometry (left: &Wand, right: &Wand, mirrored: bool) -> Geometry {
+    let mut relative = [0, 1, 2].map(|i| right.pos[i] - left.pos[i]);
+
+    if mirrored {
+        relative[0] = -relative[0];
+    }
+
+    let a = orientation::pointing(left.rot);
+    let b = orientation::pointing(right.rot);
+
+    Geometry {
+        distance: hyp(&relative),
+        relative,
+        height:   relative[1],
+        angle:    (a[0]*b[0] + a[1]*b[1] + a[2]*b[2]).clamp(-1.0, 1.0).acos(),
+        midpoint: [0, 1, 2].map(|i| (left.pos[i] + right.pos[i]) / 2.0),
+    }
+}
+
+// Gentle around the middle, steeper towards the extremes
+fn bend_curve (twist: f32) -> f32 {
+    twist.powf(3.0).clamp(-2.0, 2.0) * 0.5
+}
+
+// Mirrored, twist and yaw are flipped so the mirror image of a gesture does the
+// same thing
+fn mirror_wand (wand: &mut Wand) {
+    wand.yaw   = -wand.yaw;
+    wand.twist = -wand.twist;
+}
+
+// Readings go through the wand's calibration on the way in, and orientation comes
+// out as angles (see orientation.rs). Position is left raw for the filter.
+fn copy_frame_to_wand (frame: &ControllerFrame, wand: &mut Wand, prev_wand: &Wand, calibration: &Calibration, order: Order) {
+    wand.pos = calibration::position(calibration, frame.pos);
+    wand.rot = orientation::align(frame.rot_quat, prev_wand.rot);
+
+    let angles = calibration::angles(calibration, frame.rot_quat, order);
+
+    wand.pitch   = finite_or(angles.pitch, prev_wand.pitch);
+    wand.yaw     = finite_or(angles.yaw, prev_wand.yaw);
+    wand.twist   = finite_or(calibration::twist(calibration, angles.roll), prev_wand.twist);
+    wand.trigger = finite_or(calibration::trigger(calibration, frame.trigger), prev_wand.trigger);
 
     wand.bumper = button_mask(frame.buttons, hydra::BUTTON_BUMPER);
     wand.home   = button_mask(frame.buttons, hydra::BUTTON_HOME);
@@ -447,17 +884,25 @@ fn copy_joystick_to_wand (frame: &ControllerFrame, wand: &mut Wand) {
     wand.stick.clicked  = (frame.buttons & 0b100000000) != 0;
 }
 
-fn stick_to_note_offset(&wand: &Wand) -> i8 {
+// The root moved by the sticks, as a note MIDI can send. The root can be tuned
+// anywhere from 0 to 127, so near either end the sticks can reach past it.
+fn note_from (root: u8, offset: i8, modifier: i8) -> u8 {
+    (root as i16 + offset as i16 + modifier as i16).clamp(0, 127) as u8
+}
+
+fn stick_to_note_offset(&wand: &Wand, scale: Scale) -> i8 {
+    let degrees = scale.degrees();
+
     match wand.stick.octant {
-        Direction::Left      => -4,
-        Direction::UpLeft    => -2,
-        Direction::Up        =>  2,
-        Direction::UpRight   =>  3,
-        Direction::Right     =>  5,
-        Direction::DownRight =>  7,
-        Direction::Down      =>  8,
-        Direction::DownLeft  => 10,
-        _ => 0,
+        Direction::Left      => degrees[5] - 12,
+        Direction::UpLeft    => degrees[6] - 12,
+        Direction::Up        => degrees[1],
+        Direction::UpRight   => degrees[2],
+        Direction::Right     => degrees[3],
+        Direction::DownRight => degrees[4],
+        Direction::Down      => degrees[5],
+        Direction::DownLeft  => degrees[6],
+        _ => degrees[0],
     }
 }
 
@@ -510,7 +955,128 @@ fn joystick_octant (stick: &Joystick) -> Direction {
     }
 }
 
-fn derivative_r3 (a: &[f32;3], b: &[f32;3], delta: f32) -> [f32;3] {
-    [ (a[0] - b[0]) / delta, (a[1] - b[1]) / delta, (a[2] - b[2]) / delta ]
-}
 
+//
+// Tests
+//
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds_between_trusts_the_clock_when_it_can () {
+        assert_eq!(seconds_between(Duration::from_millis(5), 10, 9), 0.005);
+        assert_eq!(seconds_between(Duration::from_millis(5), 10, 10), 0.005);
+    }
+
+    #[test]
+    fn seconds_between_counts_samples_when_the_clock_cant () {
+        for timedelta in [Duration::ZERO, Duration::from_micros(50)] {
+            assert_eq!(seconds_between(timedelta, 10, 10), 0.0);
+            assert_eq!(seconds_between(timedelta, 11, 10), SAMPLE_PERIOD);
+            assert_eq!(seconds_between(timedelta, 13, 10), 3.0 * SAMPLE_PERIOD);
+            assert_eq!(seconds_between(timedelta, 1, 255), 2.0 * SAMPLE_PERIOD);
+        }
+    }
+
+    // Every stick position on both hands, with the root pushed to the top and
+    // back down to the bottom an octave a tick, by key and by octave button
+    #[test]
+    fn octave_keys_never_push_notes_out_of_range () {
+        use crate::session::fixtures::wand;
+
+        let directions = [(0.0, 0.0), (0.0, 1.0), (0.7, 0.7), (1.0, 0.0), (0.7, -0.7), (0.0, -1.0), (-0.7, -0.7), (-1.0, 0.0), (-0.7, 0.7)];
+
+        for left_stick in directions {
+            for right_stick in directions {
+                let mut state     = Zgicabra::new();
+                let mut filtering = Filtering::default();
+                let mut deltas    = Vec::new();
+
+                for tick in 0..40u8 {
+                    let prev = state.clone();
+                    apply_command(&mut state, if tick < 20 { DeltaEvent::OctaveUp() } else { DeltaEvent::OctaveDown() }, &mut deltas);
+
+                    let mut left  = wand(hydra::LEFT_HAND,  [-200.0, 0.0, -300.0], 1.0, tick);
+                    let mut right = wand(hydra::RIGHT_HAND, [ 200.0, 0.0, -300.0], 1.0, tick);
+                    (left.joystick_x,  left.joystick_y)  = left_stick;
+                    (right.joystick_x, right.joystick_y) = right_stick;
+
+                    update(&mut state, &prev, &mut filtering, &left, &right, Duration::from_millis(10), &mut deltas);
+                }
+
+                // And one-handed, where the octave buttons do the same
+                let mut state     = Zgicabra::new();
+                let mut filtering = Filtering::default();
+
+                for tick in 0..40u8 {
+                    let prev = state.clone();
+                    let mut left = wand(hydra::LEFT_HAND, [-200.0, 0.0, -300.0], 1.0, tick);
+                    (left.joystick_x, left.joystick_y) = left_stick;
+                    left.buttons = if tick % 2 == 1 { 0 } else if tick < 20 { hydra::BUTTON_4 } else { hydra::BUTTON_2 };
+
+                    update_one_handed(&mut state, &prev, &mut filtering, &left, Hand::Left, Duration::from_millis(10), &mut deltas);
+                }
+
+                for delta in deltas.iter() {
+                    if let DeltaEvent::NoteStart(note) | DeltaEvent::NoteChange(_, note) | DeltaEvent::NoteEnd(note) = delta {
+                        assert!(*note <= 127, "{:?} with sticks {:?} {:?}", delta, left_stick, right_stick);
+                    }
+                }
+            }
+        }
+    }
+
+    // A note held through the right wand flipping behind the base and then
+    // glitching somewhere else, while the left stick moves: the note neither
+    // stops nor changes until tracking is back, and the distance holds
+    #[test]
+    fn shaky_tracking_holds_the_note () {
+        use crate::session::fixtures::wand;
+        use crate::midi_event::MSG_NOTE_OFF;
+        use crate::midi;
+        use crate::modulation::Modulation;
+
+        let mut state      = Zgicabra::new();
+        let mut filtering  = Filtering::default();
+        let mut modulation = Modulation::new(&[]);
+
+        for tick in 0..60u8 {
+            let prev = state.clone();
+            let mut deltas      = Vec::new();
+            let mut midi_events = Vec::new();
+
+            // Flipped at 20 and stays that way, glitches at 21
+            let right_pos = match tick {
+                0..=19 => [ 200.0,   0.0, -300.0],
+                21     => [ 600.0, 400.0,  300.0],
+                _      => [-200.0,   0.0,  300.0],
+            };
+
+            let trigger = if tick == 0 || tick == 59 { 0.0 } else { 1.0 };
+            let mut left  = wand(hydra::LEFT_HAND,  [-200.0, 0.0, -300.0], trigger, tick);
+            let     right = wand(hydra::RIGHT_HAND, right_pos,             trigger, tick);
+            left.joystick_x = if tick < 21 { 0.0 } else { 1.0 };
+
+            update(&mut state, &prev, &mut filtering, &left, &right, Duration::from_millis(10), &mut deltas);
+            midi::update(&state, &mut modulation, &deltas, &mut midi_events);
+
+            let notes = midi_events.iter().filter(|event| event.msg & 0xE0 == MSG_NOTE_OFF).count();
+
+            match tick {
+                1       => assert!(matches!(deltas.first(), Some(DeltaEvent::NoteStart(_)))),
+                20..=40 => assert_eq!(notes, 0, "note events at tick {}: {:?}", tick, deltas),
+                _       => {}
+            }
+
+            if tick == 21 {
+                assert!(state.right.tracking < MIN_TRACKING);
+                assert_eq!(state.separation, prev.separation);
+                assert_eq!(state.geometry.distance, prev.geometry.distance);
+            }
+        }
+
+        assert!(!state.note.on);
+    }
+}